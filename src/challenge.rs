@@ -0,0 +1,171 @@
+use ed25519_dalek::{PublicKey as Ed25519PublicKey, Signature, Verifier};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Name of the sled tree holding outstanding challenges.
+pub const CHALLENGE_TREE: &str = "challenges";
+/// How long an issued nonce remains valid before it must be re-requested.
+const CHALLENGE_TTL_SECS: i64 = 60;
+
+/// Errors produced while issuing or verifying a proof-of-possession
+/// challenge. Free of any key material so it is safe to log or surface.
+#[derive(Error, Debug)]
+pub enum ChallengeError {
+    #[error("Failed to generate challenge nonce")]
+    NonceGenerationFailed,
+    #[error("No outstanding challenge for this key")]
+    NotFound,
+    #[error("Challenge has expired")]
+    Expired,
+    #[error("Challenge proof is invalid")]
+    InvalidProof,
+    #[error("Challenge request is malformed: {0}")]
+    Malformed(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StoredChallenge {
+    nonce: [u8; 32],
+    issued_at: i64,
+}
+
+fn now_unix() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Issues a short-lived, single-use nonce bound to `key_id`, overwriting any
+/// previously issued (and not yet consumed) challenge for that key.
+pub fn issue_challenge(challenges: &sled::Tree, key_id: &str) -> Result<[u8; 32], ChallengeError> {
+    let rng = SystemRandom::new();
+    let mut nonce = [0u8; 32];
+    rng.fill(&mut nonce)
+        .map_err(|_| ChallengeError::NonceGenerationFailed)?;
+
+    let stored = StoredChallenge {
+        nonce,
+        issued_at: now_unix(),
+    };
+    let serialized =
+        serde_json::to_vec(&stored).map_err(|e| ChallengeError::Malformed(e.to_string()))?;
+    challenges
+        .insert(key_id.as_bytes(), serialized)
+        .map_err(|e| ChallengeError::Malformed(e.to_string()))?;
+
+    Ok(nonce)
+}
+
+/// Verifies that `signature_hex` (produced by the key's registered
+/// authorization keypair) covers the nonce previously issued for `key_id`,
+/// then consumes the challenge so it cannot be replayed. The caller supplies
+/// `nonce_hex` back so a stale or mismatched nonce is rejected explicitly
+/// rather than silently re-checked against whatever is currently stored.
+pub fn verify_and_consume_challenge(
+    challenges: &sled::Tree,
+    key_id: &str,
+    authorization_pubkey_hex: &str,
+    nonce_hex: &str,
+    signature_hex: &str,
+) -> Result<(), ChallengeError> {
+    let stored_bytes = challenges
+        .get(key_id.as_bytes())
+        .map_err(|e| ChallengeError::Malformed(e.to_string()))?
+        .ok_or(ChallengeError::NotFound)?;
+    let stored: StoredChallenge = serde_json::from_slice(&stored_bytes)
+        .map_err(|e| ChallengeError::Malformed(e.to_string()))?;
+
+    // Single-use: remove the challenge up front so a second attempt, whether
+    // it succeeds or fails verification, can never replay this nonce again.
+    let _ = challenges.remove(key_id.as_bytes());
+
+    if now_unix() - stored.issued_at > CHALLENGE_TTL_SECS {
+        return Err(ChallengeError::Expired);
+    }
+
+    let nonce_bytes = hex::decode(nonce_hex)
+        .map_err(|_| ChallengeError::Malformed("nonce is not valid hex".into()))?;
+    if nonce_bytes != stored.nonce {
+        return Err(ChallengeError::InvalidProof);
+    }
+
+    let pubkey_bytes = hex::decode(authorization_pubkey_hex)
+        .map_err(|_| ChallengeError::Malformed("authorization_pubkey is not valid hex".into()))?;
+    let pubkey = Ed25519PublicKey::from_bytes(&pubkey_bytes)
+        .map_err(|_| ChallengeError::Malformed("authorization_pubkey is malformed".into()))?;
+
+    let signature_bytes = hex::decode(signature_hex)
+        .map_err(|_| ChallengeError::Malformed("signature is not valid hex".into()))?;
+    let signature_bytes: [u8; 64] = signature_bytes
+        .try_into()
+        .map_err(|_| ChallengeError::Malformed("signature has the wrong length".into()))?;
+    let signature = Signature::from(signature_bytes);
+
+    pubkey
+        .verify(&stored.nonce, &signature)
+        .map_err(|_| ChallengeError::InvalidProof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, Signer};
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn valid_proof_of_possession_is_accepted_once() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let challenges = db.open_tree(CHALLENGE_TREE).unwrap();
+        let keypair = Keypair::generate(&mut OsRng);
+
+        let nonce = issue_challenge(&challenges, "key-1").unwrap();
+        let signature = keypair.sign(&nonce);
+
+        let authorization_pubkey_hex = hex::encode(keypair.public.as_bytes());
+        let nonce_hex = hex::encode(nonce);
+        let signature_hex = hex::encode(signature.to_bytes());
+
+        assert!(verify_and_consume_challenge(
+            &challenges,
+            "key-1",
+            &authorization_pubkey_hex,
+            &nonce_hex,
+            &signature_hex,
+        )
+        .is_ok());
+
+        // Replaying the same proof against the now-consumed challenge fails.
+        assert!(verify_and_consume_challenge(
+            &challenges,
+            "key-1",
+            &authorization_pubkey_hex,
+            &nonce_hex,
+            &signature_hex,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn wrong_signer_is_rejected() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let challenges = db.open_tree(CHALLENGE_TREE).unwrap();
+        let registered = Keypair::generate(&mut OsRng);
+        let impostor = Keypair::generate(&mut OsRng);
+
+        let nonce = issue_challenge(&challenges, "key-1").unwrap();
+        let signature = impostor.sign(&nonce);
+
+        let result = verify_and_consume_challenge(
+            &challenges,
+            "key-1",
+            &hex::encode(registered.public.as_bytes()),
+            &hex::encode(nonce),
+            &hex::encode(signature.to_bytes()),
+        );
+        assert!(result.is_err());
+    }
+}
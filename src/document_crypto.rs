@@ -0,0 +1,106 @@
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use ring::rand::{SecureRandom, SystemRandom};
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use thiserror::Error;
+
+/// Errors produced while ECIES-sealing or opening a document. Free of any
+/// key or plaintext material so it is safe to log or surface.
+#[derive(Error, Debug)]
+pub enum DocumentCryptoError {
+    #[error("Failed to generate ephemeral key")]
+    EphemeralKeyGenerationFailed,
+    #[error("Failed to generate nonce")]
+    NonceGenerationFailed,
+    #[error("Encryption failed")]
+    EncryptionFailed,
+    #[error("Decryption failed")]
+    DecryptionFailed,
+}
+
+/// The output of `encrypt_document`: everything a holder of the recipient's
+/// secret key needs to recover the plaintext, and nothing else.
+pub struct EncryptedDocument {
+    pub ephemeral_pubkey: Vec<u8>,
+    pub nonce: [u8; 12],
+    pub ciphertext: Vec<u8>,
+}
+
+/// Encrypts `plaintext` to `recipient_pubkey` using ECIES: generate an
+/// ephemeral secp256k1 keypair, ECDH against the recipient's public key, and
+/// AES-256-GCM-encrypt under the resulting shared secret.
+pub fn encrypt_document(
+    recipient_pubkey: &PublicKey,
+    plaintext: &[u8],
+) -> Result<EncryptedDocument, DocumentCryptoError> {
+    let secp = Secp256k1::new();
+    let rng = SystemRandom::new();
+
+    let mut ephemeral_bytes = [0u8; 32];
+    rng.fill(&mut ephemeral_bytes)
+        .map_err(|_| DocumentCryptoError::EphemeralKeyGenerationFailed)?;
+    let ephemeral_secret = SecretKey::from_slice(&ephemeral_bytes)
+        .map_err(|_| DocumentCryptoError::EphemeralKeyGenerationFailed)?;
+    let ephemeral_pubkey = PublicKey::from_secret_key(&secp, &ephemeral_secret);
+
+    let shared_secret = SharedSecret::new(recipient_pubkey, &ephemeral_secret);
+    let aes_key = Key::from_slice(shared_secret.as_ref());
+
+    let mut nonce_bytes = [0u8; 12];
+    rng.fill(&mut nonce_bytes)
+        .map_err(|_| DocumentCryptoError::NonceGenerationFailed)?;
+
+    let cipher = Aes256Gcm::new(aes_key);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), plaintext)
+        .map_err(|_| DocumentCryptoError::EncryptionFailed)?;
+
+    Ok(EncryptedDocument {
+        ephemeral_pubkey: ephemeral_pubkey.serialize().to_vec(),
+        nonce: nonce_bytes,
+        ciphertext,
+    })
+}
+
+/// Reverses `encrypt_document`: recomputes the shared secret from the
+/// recipient's secret key and the sender's ephemeral public key, then opens
+/// the AES-256-GCM ciphertext.
+pub fn decrypt_document(
+    recipient_secret: &SecretKey,
+    ephemeral_pubkey: &PublicKey,
+    nonce: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, DocumentCryptoError> {
+    let shared_secret = SharedSecret::new(ephemeral_pubkey, recipient_secret);
+    let aes_key = Key::from_slice(shared_secret.as_ref());
+
+    let cipher = Aes256Gcm::new(aes_key);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| DocumentCryptoError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_then_decrypt_roundtrips() {
+        let secp = Secp256k1::new();
+        let recipient_secret = SecretKey::from_slice(&[11u8; 32]).unwrap();
+        let recipient_pubkey = PublicKey::from_secret_key(&secp, &recipient_secret);
+
+        let encrypted = encrypt_document(&recipient_pubkey, b"hello wallet").unwrap();
+        let ephemeral_pubkey = PublicKey::from_slice(&encrypted.ephemeral_pubkey).unwrap();
+        let decrypted = decrypt_document(
+            &recipient_secret,
+            &ephemeral_pubkey,
+            &encrypted.nonce,
+            &encrypted.ciphertext,
+        )
+        .unwrap();
+
+        assert_eq!(decrypted, b"hello wallet");
+    }
+}
@@ -1,3 +1,13 @@
+//! Networked GG20 distributed key generation tool. Runs `multi_party_ecdsa`'s
+//! `Keygen` state machine over the same SSE transport (`gg20_sm_client`) and
+//! room model used for signing, supports arbitrary `t-of-n` thresholds
+//! supplied on the CLI, and writes the resulting `LocalKey` out AES-GCM
+//! encrypted so it is a drop-in input for `gg20_signing`'s `read_local_share`.
+//!
+//! The party index is always the one `join_secure_computation` obtains from
+//! the manager's `issue_unique_idx` -- never a CLI flag -- so it necessarily
+//! agrees with the index space `wrap_secure_channel`'s `trusted_peers` and
+//! `Keygen`'s own P2P routing use, matching `gg20_signing`/`gg20_reshare`.
 use anyhow::{anyhow, Context, Result};
 use futures::StreamExt;
 use log::{debug, info, warn};
@@ -15,7 +25,10 @@ use aes_gcm::{Aes256Gcm, Key, Nonce};
 use rand::Rng;
 
 mod gg20_sm_client;
-use gg20_sm_client::join_computation;
+use gg20_sm_client::{join_computation, Identity};
+
+mod secure_channel;
+use secure_channel::{wrap_secure_channel, SealedEnvelope};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "gg20_keygen", about = "Multi-party ECDSA key generation tool")]
@@ -44,9 +57,6 @@ struct Cli {
     )]
     output: PathBuf,
 
-    #[structopt(short, long, help = "Index of this party")]
-    index: u16,
-
     #[structopt(short, long, help = "Threshold for signature reconstruction")]
     threshold: u16,
 
@@ -54,16 +64,68 @@ struct Cli {
     number_of_parties: u16,
 }
 
+/// Joins a computation room and layers per-pair authenticated encryption on
+/// top, deriving trusted peer keys from the manager's party registry so the
+/// keygen protocol picks up end-to-end encryption transparently. Each peer's
+/// X25519 key is checked against its ed25519 binding signature
+/// (`PartyKeys::verify_x25519_binding`) before it is trusted for ECDH, so a
+/// manager that swaps a peer's registered X25519 key cannot MITM the sealed
+/// channel without also forging that peer's ed25519 signature.
+async fn join_secure_computation<M>(
+    address: surf::Url,
+    room: &str,
+    identity: &Identity,
+) -> Result<(
+    u16,
+    impl futures::Stream<Item = Result<round_based::Msg<M>>>,
+    impl futures::Sink<round_based::Msg<M>, Error = anyhow::Error>,
+)>
+where
+    M: serde::Serialize + serde::de::DeserializeOwned + Send + 'static,
+{
+    let (i, incoming, outgoing) =
+        join_computation::<SealedEnvelope>(address.clone(), room, identity)
+            .await
+            .context("Failed to join computation")?;
+
+    let sm_client = gg20_sm_client::SmClient::new(address, room)
+        .context("Failed to construct SmClient for party discovery")?;
+    let registered = sm_client
+        .parties()
+        .await
+        .context("Failed to fetch registered parties")?;
+
+    let mut trusted_peers = std::collections::HashMap::new();
+    for (idx, keys) in registered {
+        if idx == i {
+            continue;
+        }
+        let x25519_public_key = keys
+            .verify_x25519_binding()
+            .with_context(|| format!("Peer {} presented an unverifiable X25519 key binding", idx))?;
+        trusted_peers.insert(idx, x25519_public_key);
+    }
+
+    let (incoming, outgoing) = wrap_secure_channel(
+        i,
+        identity.x25519_secret.clone(),
+        trusted_peers,
+        incoming,
+        outgoing,
+    );
+    Ok((i, incoming, outgoing))
+}
+
 /// Execute the key generation protocol
 async fn execute_keygen(
     address: surf::Url,
     room: &str,
-    index: u16,
     threshold: u16,
     number_of_parties: u16,
+    identity: &Identity,
 ) -> Result<LocalKey<Secp256k1>> {
     info!("Joining key generation computation room: {}", room);
-    let (_i, incoming, outgoing) = join_computation(address, room)
+    let (i, incoming, outgoing) = join_secure_computation(address, room, identity)
         .await
         .context("Failed to join computation")?;
 
@@ -73,7 +135,7 @@ async fn execute_keygen(
 
     info!("Initializing Keygen protocol");
     let keygen =
-        Keygen::new(index, threshold, number_of_parties).context("Failed to initialize Keygen")?;
+        Keygen::new(i, threshold, number_of_parties).context("Failed to initialize Keygen")?;
 
     info!("Running Keygen protocol");
     AsyncProtocol::new(keygen, incoming, outgoing)
@@ -127,13 +189,20 @@ async fn main() -> Result<()> {
         return Err(anyhow!("Invalid threshold"));
     }
 
-    // Execute key generation
+    // Generate a fresh end-to-end encryption identity for this run.
+    let identity = Identity::generate();
+
+    // Execute key generation. The party index comes from the manager's
+    // issue_unique_idx (see join_secure_computation), not a CLI flag: it has
+    // to agree with the index space wrap_secure_channel's trusted_peers and
+    // Keygen's own P2P routing use, both of which are keyed by that
+    // manager-issued index.
     let local_key = execute_keygen(
         args.address,
         &args.room,
-        args.index,
         args.threshold,
         args.number_of_parties,
+        &identity,
     )
     .await?;
 
@@ -0,0 +1,336 @@
+//! Proactive share refresh ("resharing") for an existing GG20 key. Lets the
+//! parties that hold shares of a `LocalKey<Secp256k1>` periodically rotate
+//! those shares while keeping the same aggregate public key, limiting the
+//! window in which a single compromised share is useful — analogous to
+//! `updateSeraiKey`-style rotation flows that move secret material without
+//! moving the funds controlled by it to a new address.
+//!
+//! The refresh is a standard proactive secret sharing round: every party
+//! deals a fresh `(threshold, number_of_parties)` Shamir sharing of zero,
+//! sends each other party its share of that zero-polynomial, and each party
+//! sums the `n` zero-shares it receives (one per dealer, including its own)
+//! into its existing secret share. Because every dealt polynomial evaluates
+//! to zero at `x = 0`, the sum of the *new* shares still reconstructs the
+//! original secret, but no individual share from before the round is useful
+//! on its own afterwards.
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use log::{debug, info, warn};
+use structopt::StructOpt;
+
+use curv::arithmetic::Converter;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use curv::elliptic::curves::{Point, Scalar};
+use curv::BigInt;
+
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::LocalKey;
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::Rng;
+use round_based::Msg;
+
+mod gg20_sm_client;
+use gg20_sm_client::{join_computation, Identity};
+
+mod secure_channel;
+use secure_channel::{wrap_secure_channel, SealedEnvelope};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "gg20_reshare", about = "Proactive GG20 share refresh tool")]
+struct Cli {
+    #[structopt(
+        short,
+        long,
+        default_value = "http://localhost:8000/",
+        help = "Address of the state machine manager"
+    )]
+    address: surf::Url,
+
+    #[structopt(
+        short,
+        long,
+        default_value = "default-reshare",
+        help = "Room identifier for the refresh round"
+    )]
+    room: String,
+
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        help = "Path to the existing encrypted local share"
+    )]
+    local_share: PathBuf,
+
+    #[structopt(short, long, help = "Encryption key of the existing share, in hex")]
+    key: String,
+
+    #[structopt(short, long, help = "Nonce of the existing share, in hex")]
+    nonce: String,
+
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        help = "Path to save the freshly encrypted share"
+    )]
+    output: PathBuf,
+}
+
+/// One dealer's contribution to a refresh round: the Feldman commitments to
+/// its zero-polynomial (broadcast, so every party can verify the share it
+/// receives) and, on the wire, the share itself (sent point-to-point via the
+/// existing `Msg::receiver` targeting already used by `join_computation`).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+enum ReshareMsg {
+    Commitment(VerifiableSS<Secp256k1>),
+    Share(BigInt),
+}
+
+/// Reads and decrypts the existing local share, mirroring `gg20_signing`'s
+/// `read_local_share`.
+async fn read_local_share(path: &PathBuf, key: &[u8], nonce: &[u8]) -> Result<LocalKey<Secp256k1>> {
+    info!("Reading encrypted local share from {:?}", path);
+    let encrypted_data = tokio::fs::read(path)
+        .await
+        .context("Failed to read encrypted local share file")?;
+
+    let cipher = Aes256Gcm::new(Key::from_slice(key));
+    let decrypted = cipher
+        .decrypt(Nonce::from_slice(nonce), encrypted_data.as_ref())
+        .map_err(|e| anyhow!("Decryption failed: {:?}", e))?;
+
+    serde_json::from_slice(&decrypted).context("Failed to parse decrypted local share")
+}
+
+/// Encrypts the refreshed local share under a freshly generated key/nonce,
+/// the same scheme `gg20_keygen` uses for its output.
+fn encrypt_local_share(local_key: &LocalKey<Secp256k1>) -> Result<(Vec<u8>, [u8; 32], [u8; 12])> {
+    let key = rand::thread_rng().gen::<[u8; 32]>();
+    let nonce = rand::thread_rng().gen::<[u8; 12]>();
+
+    let cipher = Aes256Gcm::new(Key::from_slice(&key));
+    let serialized = serde_json::to_vec(local_key).context("Failed to serialize refreshed share")?;
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce), serialized.as_ref())
+        .map_err(|e| anyhow!("Encryption failed: {:?}", e))?;
+
+    Ok((encrypted, key, nonce))
+}
+
+/// Joins a computation room and layers per-pair authenticated encryption on
+/// top, deriving trusted peer keys from the manager's party registry so the
+/// reshare round picks up end-to-end encryption transparently. Each peer's
+/// X25519 key is checked against its ed25519 binding signature
+/// (`PartyKeys::verify_x25519_binding`) before it is trusted for ECDH, so a
+/// manager that swaps a peer's registered X25519 key cannot MITM the sealed
+/// channel without also forging that peer's ed25519 signature.
+async fn join_secure_computation<M>(
+    address: surf::Url,
+    room: &str,
+    identity: &Identity,
+) -> Result<(
+    u16,
+    impl futures::Stream<Item = Result<Msg<M>>>,
+    impl futures::Sink<Msg<M>, Error = anyhow::Error>,
+)>
+where
+    M: serde::Serialize + serde::de::DeserializeOwned + Send + 'static,
+{
+    let (i, incoming, outgoing) =
+        join_computation::<SealedEnvelope>(address.clone(), room, identity)
+            .await
+            .context("Failed to join computation")?;
+
+    let sm_client = gg20_sm_client::SmClient::new(address, room)
+        .context("Failed to construct SmClient for party discovery")?;
+    let registered = sm_client
+        .parties()
+        .await
+        .context("Failed to fetch registered parties")?;
+
+    let mut trusted_peers = std::collections::HashMap::new();
+    for (idx, keys) in registered {
+        if idx == i {
+            continue;
+        }
+        let x25519_public_key = keys
+            .verify_x25519_binding()
+            .with_context(|| format!("Peer {} presented an unverifiable X25519 key binding", idx))?;
+        trusted_peers.insert(idx, x25519_public_key);
+    }
+
+    let (incoming, outgoing) = wrap_secure_channel(
+        i,
+        identity.x25519_secret.clone(),
+        trusted_peers,
+        incoming,
+        outgoing,
+    );
+    Ok((i, incoming, outgoing))
+}
+
+/// Runs one proactive-resharing round over `join_computation` and returns the
+/// refreshed local share with the same aggregate public key as `local_key`.
+async fn execute_reshare(
+    address: surf::Url,
+    room: &str,
+    mut local_key: LocalKey<Secp256k1>,
+    identity: &Identity,
+) -> Result<LocalKey<Secp256k1>> {
+    let number_of_parties = local_key.n;
+    let threshold = local_key.t;
+
+    info!("Joining reshare computation room: {}", room);
+    let (i, incoming, outgoing) = join_secure_computation::<ReshareMsg>(address, room, identity)
+        .await
+        .context("Failed to join reshare computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    // Deal a fresh (threshold, number_of_parties) Shamir sharing of zero.
+    let (vss, my_shares) = VerifiableSS::share(threshold, number_of_parties, &Scalar::zero());
+
+    debug!("Broadcasting zero-sharing commitment for party {}", i);
+    outgoing
+        .send(Msg {
+            sender: i,
+            receiver: None,
+            body: ReshareMsg::Commitment(vss.clone()),
+        })
+        .await
+        .context("Failed to broadcast VSS commitment")?;
+
+    for receiver in 1..=number_of_parties {
+        if receiver == i {
+            // Our own share is already folded into `refresh_term` below
+            // without going over the wire; `wrap_secure_channel` has no
+            // secure channel keyed to our own index (`trusted_peers`
+            // excludes `i`), so sending here would just error out.
+            continue;
+        }
+        let share = &my_shares[usize::from(receiver) - 1];
+        outgoing
+            .send(Msg {
+                sender: i,
+                receiver: Some(receiver),
+                body: ReshareMsg::Share(share.to_bigint()),
+            })
+            .await
+            .context("Failed to send zero-share")?;
+    }
+
+    // Collect every other dealer's commitment and the share it sent us,
+    // verify the share against the commitment, and accumulate the refresh
+    // term. We expect (number_of_parties - 1) commitments and shares from
+    // everyone else, plus our own dealt share to ourselves.
+    //
+    // A dealer's Share can arrive before its Commitment over this transport
+    // (chunk1-4's own docs call this out as a real possibility), so a share
+    // with no commitment yet is buffered in `pending_shares` rather than
+    // folded into `refresh_term` unverified; it's validated and applied as
+    // soon as its commitment shows up, from either message order.
+    let mut commitments = std::collections::HashMap::new();
+    let mut pending_shares: std::collections::HashMap<u16, BigInt> = std::collections::HashMap::new();
+    let mut refresh_term = my_shares[usize::from(i) - 1].clone();
+    let mut shares_received = 0usize;
+
+    while shares_received < usize::from(number_of_parties) - 1 || commitments.len() < usize::from(number_of_parties) - 1 {
+        let msg = incoming
+            .next()
+            .await
+            .ok_or_else(|| anyhow!("Reshare round ended before all parties reported in"))??;
+
+        match msg.body {
+            ReshareMsg::Commitment(vss) => {
+                if let Some(share_bigint) = pending_shares.remove(&msg.sender) {
+                    let share = Scalar::from_bigint(&share_bigint);
+                    vss.validate_share(&share, i).map_err(|_| {
+                        anyhow!("Zero-share from party {} failed VSS verification", msg.sender)
+                    })?;
+                    refresh_term = refresh_term + share;
+                    shares_received += 1;
+                }
+                commitments.insert(msg.sender, vss);
+            }
+            ReshareMsg::Share(share_bigint) => {
+                if let Some(vss) = commitments.get(&msg.sender) {
+                    let share = Scalar::from_bigint(&share_bigint);
+                    vss.validate_share(&share, i).map_err(|_| {
+                        anyhow!("Zero-share from party {} failed VSS verification", msg.sender)
+                    })?;
+                    refresh_term = refresh_term + share;
+                    shares_received += 1;
+                } else {
+                    pending_shares.insert(msg.sender, share_bigint);
+                }
+            }
+        }
+    }
+
+    local_key.keys_linear.x_i = local_key.keys_linear.x_i + refresh_term;
+
+    // The Feldman commitments every dealer broadcast are public, so -- unlike
+    // the shares themselves -- every party can independently recompute the
+    // public increment each party's share just received: the sum, over every
+    // dealt zero-polynomial (including our own `vss`), of that polynomial's
+    // commitment evaluated at the party's index. Folding that into `pk_vec`
+    // keeps the refreshed `LocalKey`'s public data consistent with the new
+    // secret shares, so the next signing round's offline stage (which
+    // validates against `pk_vec`) doesn't reject -- or silently diverge
+    // against -- a key whose public half still reflected the pre-refresh
+    // shares.
+    let all_commitments: Vec<&VerifiableSS<Secp256k1>> =
+        commitments.values().chain(std::iter::once(&vss)).collect();
+    for idx in 1..=number_of_parties {
+        let mut public_refresh_term = Point::<Secp256k1>::zero();
+        for dealer_vss in &all_commitments {
+            public_refresh_term = public_refresh_term + dealer_vss.get_point_commitment(idx);
+        }
+        let slot = &mut local_key.pk_vec[usize::from(idx) - 1];
+        *slot = slot.clone() + public_refresh_term;
+    }
+
+    info!("Reshare round completed for party {}", i);
+    Ok(local_key)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let args: Cli = Cli::from_args();
+    info!("Starting proactive share refresh");
+
+    let key = hex::decode(&args.key).context("Failed to decode encryption key")?;
+    let nonce = hex::decode(&args.nonce).context("Failed to decode nonce")?;
+
+    let local_key = read_local_share(&args.local_share, &key, &nonce).await?;
+    let old_public_key = local_key.public_key();
+
+    let identity = Identity::generate();
+    let refreshed = execute_reshare(args.address, &args.room, local_key, &identity).await?;
+
+    if refreshed.public_key() != old_public_key {
+        warn!("Refreshed share no longer reconstructs the original public key");
+        return Err(anyhow!("Reshare invariant violated: public key changed"));
+    }
+
+    let (encrypted, key, nonce) = encrypt_local_share(&refreshed)?;
+    tokio::fs::write(&args.output, &encrypted)
+        .await
+        .context("Failed to save refreshed encrypted share")?;
+
+    info!("Refreshed encrypted share saved to {:?}", args.output);
+    println!("Encryption key (hex): {}", hex::encode(key));
+    println!("Nonce (hex): {}", hex::encode(nonce));
+
+    Ok(())
+}
@@ -20,12 +20,22 @@ use aes_gcm::aead::{Aead, NewAead};
 use aes_gcm::{Aes256Gcm, Key, Nonce};
 
 mod gg20_sm_client;
-use gg20_sm_client::join_computation;
+use gg20_sm_client::Identity;
 
 mod paillier_validator;
 use paillier_validator::{PaillierValidator, ValidationResult};
 
-use surf;
+mod secure_channel;
+use secure_channel::{wrap_secure_channel, SealedEnvelope};
+
+mod transport;
+use transport::{channels_for, FramedTransport};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use futures::{Sink, Stream};
 
 /// Command-line interface structure for the GG20 signing tool
 #[derive(Debug, StructOpt)]
@@ -78,6 +88,140 @@ struct Cli {
     /// Data to be signed
     #[structopt(short, long, help = "Data to be signed")]
     data_to_sign: String,
+
+    /// Listen address for the direct mesh transport. The hosted SM manager
+    /// is still used to issue this party's index and publish its keys, but
+    /// protocol traffic for the (multi-round) offline stage runs directly
+    /// between parties instead of through the manager. Requires `--mesh-peer`
+    /// for every other participating party.
+    #[structopt(long, help = "Enable the direct mesh transport, listening on this address")]
+    mesh_listen: Option<SocketAddr>,
+
+    /// A mesh peer's address, given as `index=host:port`; repeat once per
+    /// other participating party. Only used when `--mesh-listen` is set.
+    #[structopt(
+        long,
+        help = "Mesh peer address as index=host:port (repeatable); required with --mesh-listen"
+    )]
+    mesh_peer: Vec<String>,
+}
+
+/// Where the offline stage's protocol traffic should run.
+enum ChannelTransport {
+    /// The hosted SM manager's SSE/HTTP relay (the default).
+    Sse,
+    /// A direct, length-delimited TCP mesh between parties.
+    Mesh {
+        listen_addr: SocketAddr,
+        peer_addrs: HashMap<u16, SocketAddr>,
+    },
+}
+
+impl ChannelTransport {
+    fn from_cli(mesh_listen: Option<SocketAddr>, mesh_peer: &[String]) -> Result<Self> {
+        let listen_addr = match mesh_listen {
+            Some(addr) => addr,
+            None => return Ok(ChannelTransport::Sse),
+        };
+
+        let mut peer_addrs = HashMap::with_capacity(mesh_peer.len());
+        for entry in mesh_peer {
+            let (idx, addr) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("--mesh-peer entries must look like index=host:port, got {}", entry))?;
+            let idx: u16 = idx
+                .parse()
+                .with_context(|| format!("Invalid mesh peer index in {}", entry))?;
+            let addr: SocketAddr = addr
+                .parse()
+                .with_context(|| format!("Invalid mesh peer address in {}", entry))?;
+            peer_addrs.insert(idx, addr);
+        }
+
+        Ok(ChannelTransport::Mesh {
+            listen_addr,
+            peer_addrs,
+        })
+    }
+}
+
+/// Joins a computation room over either the hosted SM manager or a direct
+/// mesh, then layers per-pair authenticated encryption on top, deriving
+/// trusted peer keys from the manager's party registry so the offline and
+/// online stages pick up end-to-end encryption transparently regardless of
+/// which transport carries the bytes. Each peer's X25519 key is checked
+/// against its ed25519 binding signature (`PartyKeys::verify_x25519_binding`)
+/// before it is trusted for ECDH, so a manager that swaps a peer's registered
+/// X25519 key cannot MITM the sealed channel without also forging that
+/// peer's ed25519 signature.
+async fn join_secure_computation<M>(
+    address: surf::Url,
+    room: &str,
+    identity: &Identity,
+    transport: &ChannelTransport,
+) -> Result<(
+    u16,
+    Pin<Box<dyn Stream<Item = Result<Msg<M>>> + Send>>,
+    Pin<Box<dyn Sink<Msg<M>, Error = anyhow::Error> + Send>>,
+)>
+where
+    M: serde::Serialize + serde::de::DeserializeOwned + Send + 'static,
+{
+    // The manager is always used for index assignment and key-discovery
+    // rendezvous, even when protocol traffic itself runs over the mesh.
+    let rendezvous_client =
+        gg20_sm_client::SmClient::new(address.clone(), room).context("Failed to construct SmClient")?;
+    let i = rendezvous_client
+        .issue_index(&identity.as_party_keys())
+        .await
+        .context("Failed to issue an index")?;
+
+    let registered = rendezvous_client
+        .parties()
+        .await
+        .context("Failed to fetch registered parties")?;
+    let mut trusted_peers = HashMap::new();
+    for (idx, keys) in registered {
+        if idx == i {
+            continue;
+        }
+        let x25519_public_key = keys
+            .verify_x25519_binding()
+            .with_context(|| format!("Peer {} presented an unverifiable X25519 key binding", idx))?;
+        trusted_peers.insert(idx, x25519_public_key);
+    }
+
+    let (incoming, outgoing): (
+        Pin<Box<dyn Stream<Item = Result<Msg<SealedEnvelope>>> + Send>>,
+        Pin<Box<dyn Sink<Msg<SealedEnvelope>, Error = anyhow::Error> + Send>>,
+    ) = match transport {
+        ChannelTransport::Sse => {
+            let channel_client = gg20_sm_client::SmClient::new(address, room)
+                .context("Failed to construct SmClient for the SSE channel")?;
+            let (incoming, outgoing) =
+                gg20_sm_client::open_sse_channel(channel_client, i, identity).await?;
+            (Box::pin(incoming), Box::pin(outgoing))
+        }
+        ChannelTransport::Mesh {
+            listen_addr,
+            peer_addrs,
+        } => {
+            let mesh = FramedTransport::connect(i, *listen_addr, peer_addrs.clone())
+                .await
+                .context("Failed to establish mesh transport")?;
+            let (_i, incoming, outgoing) = channels_for(mesh);
+            (Box::pin(incoming), Box::pin(outgoing))
+        }
+    };
+
+    let (incoming, outgoing) = wrap_secure_channel(
+        i,
+        identity.x25519_secret.clone(),
+        trusted_peers,
+        incoming,
+        outgoing,
+    );
+    Ok((i, Box::pin(incoming), Box::pin(outgoing)))
 }
 
 /// Read and decrypt the local share from a file
@@ -113,12 +257,15 @@ async fn execute_offline_stage(
     room: &str,
     parties: Vec<u16>,
     local_share: LocalKey<Secp256k1>,
+    identity: &Identity,
+    transport: &ChannelTransport,
 ) -> Result<CompletedOfflineStage> {
     info!("Joining offline computation room: {}-offline", room);
     // Join the computation room for the offline stage
-    let (i, incoming, outgoing) = join_computation(address, &format!("{}-offline", room))
-        .await
-        .context("Failed to join offline computation")?;
+    let (i, incoming, outgoing) =
+        join_secure_computation(address, &format!("{}-offline", room), identity, transport)
+            .await
+            .context("Failed to join offline computation")?;
 
     let incoming = incoming.fuse();
     tokio::pin!(incoming);
@@ -167,12 +314,20 @@ async fn execute_online_stage(
     data_to_sign: &str,
     completed_offline_stage: CompletedOfflineStage,
     number_of_parties: usize,
+    identity: &Identity,
 ) -> Result<String> {
     info!("Joining online computation room: {}-online", room);
-    // Join the computation room for the online stage
-    let (i, incoming, outgoing) = join_computation(address, &format!("{}-online", room))
-        .await
-        .context("Failed to join online computation")?;
+    // Join the computation room for the online stage. The online stage is a
+    // single broadcast round-trip, so it always goes through the hosted SM
+    // manager even when the offline stage used the direct mesh transport.
+    let (i, incoming, outgoing) = join_secure_computation(
+        address,
+        &format!("{}-online", room),
+        identity,
+        &ChannelTransport::Sse,
+    )
+    .await
+    .context("Failed to join online computation")?;
 
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
@@ -240,9 +395,24 @@ async fn main() -> Result<()> {
         return Err(anyhow!("Insufficient number of parties"));
     }
 
+    // Generate a fresh end-to-end encryption identity for this run; the same
+    // identity is registered in both the offline and the online room.
+    let identity = Identity::generate();
+
+    // The direct mesh transport, if requested, carries only the offline
+    // stage's protocol traffic; see `ChannelTransport`.
+    let channel_transport = ChannelTransport::from_cli(args.mesh_listen, &args.mesh_peer)?;
+
     // Execute the offline stage of the signing protocol
-    let completed_offline_stage =
-        execute_offline_stage(args.address.clone(), &args.room, args.parties, local_share).await?;
+    let completed_offline_stage = execute_offline_stage(
+        args.address.clone(),
+        &args.room,
+        args.parties,
+        local_share,
+        &identity,
+        &channel_transport,
+    )
+    .await?;
 
     info!("Offline stage completed successfully");
 
@@ -253,6 +423,7 @@ async fn main() -> Result<()> {
         &args.data_to_sign,
         completed_offline_stage,
         number_of_parties,
+        &identity,
     )
     .await?;
 
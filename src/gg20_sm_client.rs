@@ -1,19 +1,154 @@
 use std::convert::TryInto;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use ed25519_dalek::{
+    Keypair as Ed25519Keypair, PublicKey as Ed25519PublicKey, Signature, Signer, Verifier,
+};
 use futures::{Sink, Stream, StreamExt, TryStreamExt};
 use log::{debug, info};
+use rand::rngs::OsRng;
+use rand::Rng;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use structopt::StructOpt;
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret as X25519StaticSecret};
 
 use round_based::Msg;
 
+/// A party's long-lived identity: an ed25519 key pair that authenticates its
+/// broadcasts to the SM manager (mirroring `gg20_sm_manager`'s `PartyKeys`
+/// registration) and an X25519 key pair other parties use to derive a
+/// per-pair symmetric key, as consumed by `secure_channel::wrap_secure_channel`.
+pub struct Identity {
+    pub ed25519_keypair: Ed25519Keypair,
+    pub x25519_secret: X25519StaticSecret,
+}
+
+impl Identity {
+    /// Generates a fresh, random identity. Callers that need a stable
+    /// identity across runs (so peers' registered keys stay valid) should
+    /// persist the returned key material themselves.
+    pub fn generate() -> Self {
+        Self {
+            ed25519_keypair: Ed25519Keypair::generate(&mut OsRng),
+            x25519_secret: X25519StaticSecret::new(&mut OsRng),
+        }
+    }
+
+    pub fn x25519_public(&self) -> X25519PublicKey {
+        X25519PublicKey::from(&self.x25519_secret)
+    }
+
+    /// Builds the `PartyKeys` registration payload for this identity, as
+    /// sent to the manager by `join_computation` and by callers (such as
+    /// `transport::FramedTransport` users) that issue an index directly.
+    ///
+    /// The X25519 key is signed with the ed25519 key so that a peer who
+    /// already trusts our ed25519 identity (e.g. from a prior session, or
+    /// out-of-band) can verify the X25519 key came from us too, rather than
+    /// from a manager that swapped it in transit. See `verify_x25519_binding`.
+    pub fn as_party_keys(&self) -> PartyKeys {
+        let x25519_public_key = hex::encode(self.x25519_public().as_bytes());
+        let signature = self
+            .ed25519_keypair
+            .sign(&x25519_binding_payload(&x25519_public_key));
+        PartyKeys {
+            ed25519_public_key: hex::encode(self.ed25519_keypair.public.as_bytes()),
+            x25519_public_key,
+            x25519_signature: hex::encode(signature.to_bytes()),
+        }
+    }
+}
+
+/// Domain-separates the X25519 binding signature from other ed25519
+/// signatures this identity produces (e.g. `signed_payload` broadcasts),
+/// so a signature can never be replayed across the two purposes.
+fn x25519_binding_payload(x25519_public_key_hex: &str) -> Vec<u8> {
+    const CONTEXT: &[u8] = b"wallet_as_service.x25519_binding.v1";
+    let mut payload = Vec::with_capacity(CONTEXT.len() + x25519_public_key_hex.len());
+    payload.extend_from_slice(CONTEXT);
+    payload.extend_from_slice(x25519_public_key_hex.as_bytes());
+    payload
+}
+
+/// The identity keys a party registers with the manager when it joins a room,
+/// matching `gg20_sm_manager`'s `PartyKeys`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartyKeys {
+    pub ed25519_public_key: String,
+    pub x25519_public_key: String,
+    /// The registering party's own ed25519 signature over `x25519_public_key`
+    /// (see `Identity::as_party_keys`). The manager stores and relays this
+    /// verbatim without checking it; callers that are about to trust
+    /// `x25519_public_key` for ECDH (e.g. `secure_channel::wrap_secure_channel`)
+    /// MUST call `verify_x25519_binding` first, since the manager is not
+    /// trusted to have kept the two keys from being swapped independently.
+    /// Defaults to empty when absent (e.g. a peer still running a
+    /// pre-upgrade client), which simply fails verification rather than
+    /// this struct failing to deserialize at all.
+    #[serde(default)]
+    pub x25519_signature: String,
+}
+
+impl PartyKeys {
+    /// Verifies that `x25519_public_key` was signed by the holder of
+    /// `ed25519_public_key`, and returns the verified X25519 key. Callers
+    /// must already trust `ed25519_public_key` itself (e.g. because they
+    /// recognize it from a prior session or an out-of-band exchange) --
+    /// this only proves the two keys are bound together, not that the
+    /// ed25519 key belongs to who a caller thinks it does.
+    pub fn verify_x25519_binding(&self) -> Result<X25519PublicKey> {
+        let ed25519_public_key_bytes =
+            hex::decode(&self.ed25519_public_key).context("ed25519 public key is not valid hex")?;
+        let ed25519_public_key = Ed25519PublicKey::from_bytes(&ed25519_public_key_bytes)
+            .context("ed25519 public key is malformed")?;
+        let signature_bytes =
+            hex::decode(&self.x25519_signature).context("x25519 binding signature is not valid hex")?;
+        let signature =
+            Signature::from_bytes(&signature_bytes).context("x25519 binding signature is malformed")?;
+        ed25519_public_key
+            .verify(&x25519_binding_payload(&self.x25519_public_key), &signature)
+            .context("x25519 binding signature does not verify against the ed25519 identity")?;
+        let x25519_public_key_bytes: [u8; 32] = hex::decode(&self.x25519_public_key)
+            .context("x25519 public key is not valid hex")?
+            .try_into()
+            .map_err(|_| anyhow!("x25519 public key is not 32 bytes"))?;
+        Ok(X25519PublicKey::from(x25519_public_key_bytes))
+    }
+}
+
+/// A sealed, ed25519-authenticated message as relayed through the SM manager.
+/// Matches `gg20_sm_manager`'s private `SealedEnvelope` wire format.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct WireEnvelope {
+    sender_idx: u16,
+    nonce: String,
+    ciphertext: String,
+    ed25519_signature: String,
+}
+
+/// Builds the exact byte payload the manager signs/verifies over, so the
+/// signature here matches what `gg20_sm_manager::signed_payload` computes.
+fn signed_payload(sender_idx: u16, nonce: &str, ciphertext: &str) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(2 + nonce.len() + ciphertext.len());
+    payload.extend_from_slice(&sender_idx.to_be_bytes());
+    payload.extend_from_slice(nonce.as_bytes());
+    payload.extend_from_slice(ciphertext.as_bytes());
+    payload
+}
+
 /// Joins a computation by connecting to a state machine manager.
 ///
-/// This function sets up the communication channels for a party to participate in a multi-party computation.
+/// Registers `identity`'s public keys with the manager, then sets up the
+/// communication channels for a party to participate in a multi-party
+/// computation. Outgoing messages are signed with `identity`'s ed25519 key so
+/// the manager can authenticate their origin; this function does not by
+/// itself provide confidentiality against the manager or other parties --
+/// callers that need that should wrap the returned channels with
+/// `secure_channel::wrap_secure_channel`.
 pub async fn join_computation<M>(
     address: surf::Url,
     room_id: &str,
+    identity: &Identity,
 ) -> Result<(
     u16,
     impl Stream<Item = Result<Msg<M>>>,
@@ -27,23 +162,46 @@ where
         address, room_id
     );
     let client = SmClient::new(address, room_id).context("Failed to construct SmClient")?;
+    let index = client
+        .issue_index(&identity.as_party_keys())
+        .await
+        .context("Failed to issue an index")?;
+    debug!("Obtained party index: {}", index);
 
+    let (incoming, outgoing) = open_sse_channel(client, index, identity).await?;
+    Ok((index, incoming, outgoing))
+}
+
+/// Builds the SSE subscribe/broadcast channel for a room, given a party
+/// index already issued by the manager (e.g. via `SmClient::issue_index`).
+/// Split out from `join_computation` so a caller that only wants the
+/// manager for index and key-discovery rendezvous -- routing the actual
+/// protocol traffic elsewhere, such as `transport::FramedTransport` -- can
+/// issue the index once and skip opening this channel entirely.
+pub async fn open_sse_channel<M>(
+    client: SmClient,
+    index: u16,
+    identity: &Identity,
+) -> Result<(
+    impl Stream<Item = Result<Msg<M>>>,
+    impl Sink<Msg<M>, Error = anyhow::Error>,
+)>
+where
+    M: Serialize + DeserializeOwned,
+{
     // Construct channel of incoming messages
     let incoming = client
         .subscribe()
         .await
         .context("Failed to subscribe")?
-        .and_then(|msg| async move {
-            serde_json::from_str::<Msg<M>>(&msg).context("Failed to deserialize message")
+        .and_then(|envelope| async move {
+            let ciphertext = hex::decode(&envelope.ciphertext)
+                .context("Envelope ciphertext is not valid hex")?;
+            let msg: Msg<M> =
+                serde_json::from_slice(&ciphertext).context("Failed to deserialize message")?;
+            Ok(msg)
         });
 
-    // Obtain party index
-    let index = client
-        .issue_index()
-        .await
-        .context("Failed to issue an index")?;
-    debug!("Obtained party index: {}", index);
-
     // Ignore incoming messages addressed to someone else
     let incoming = incoming.try_filter(move |msg| {
         futures::future::ready(
@@ -51,17 +209,32 @@ where
         )
     });
 
-    // Construct channel of outgoing messages
-    let outgoing = futures::sink::unfold(client, |client, message: Msg<M>| async move {
-        let serialized = serde_json::to_string(&message).context("Failed to serialize message")?;
-        client
-            .broadcast(&serialized)
-            .await
-            .context("Failed to broadcast message")?;
-        Ok::<_, anyhow::Error>(client)
-    });
+    // Construct channel of outgoing messages, each signed with our identity
+    let signing_key = Ed25519Keypair::from_bytes(&identity.ed25519_keypair.to_bytes())
+        .context("Failed to clone signing key for outgoing channel")?;
+    let outgoing = futures::sink::unfold(
+        (client, signing_key, index),
+        |(client, signing_key, index), message: Msg<M>| async move {
+            let serialized = serde_json::to_vec(&message).context("Failed to serialize message")?;
+            let nonce = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+            let ciphertext = hex::encode(&serialized);
+            let payload = signed_payload(index, &nonce, &ciphertext);
+            let signature = signing_key.sign(&payload);
+            let envelope = WireEnvelope {
+                sender_idx: index,
+                nonce,
+                ciphertext,
+                ed25519_signature: hex::encode(signature.to_bytes()),
+            };
+            client
+                .broadcast(&envelope)
+                .await
+                .context("Failed to broadcast message")?;
+            Ok::<_, anyhow::Error>((client, signing_key, index))
+        },
+    );
 
-    Ok((index, incoming, outgoing))
+    Ok((incoming, outgoing))
 }
 
 /// Represents a client for the state machine manager.
@@ -89,31 +262,45 @@ impl SmClient {
         })
     }
 
-    /// Requests a unique index from the state machine manager.
-    pub async fn issue_index(&self) -> Result<u16> {
+    /// Requests a unique index from the state machine manager, registering
+    /// `keys` against it.
+    pub async fn issue_index(&self, keys: &PartyKeys) -> Result<u16> {
         debug!("Issuing unique index");
         let response = self
             .http_client
             .post("issue_unique_idx")
+            .body(surf::Body::from_json(keys).map_err(|e| e.into_inner())?)
             .recv_json::<IssuedUniqueIdx>()
             .await
             .map_err(|e| e.into_inner())?;
         Ok(response.unique_idx)
     }
 
-    /// Broadcasts a message to all parties in the computation.
-    pub async fn broadcast(&self, message: &str) -> Result<()> {
+    /// Fetches the identity keys every party currently registered in this
+    /// room has published, keyed by party index.
+    pub async fn parties(&self) -> Result<std::collections::HashMap<u16, PartyKeys>> {
+        debug!("Fetching registered party keys");
+        self.http_client
+            .get("parties")
+            .recv_json()
+            .await
+            .map_err(|e| e.into_inner())
+    }
+
+    /// Broadcasts a sealed, authenticated envelope to all parties in the
+    /// computation.
+    pub async fn broadcast(&self, envelope: &WireEnvelope) -> Result<()> {
         debug!("Broadcasting message");
         self.http_client
             .post("broadcast")
-            .body(message)
+            .body(surf::Body::from_json(envelope).map_err(|e| e.into_inner())?)
             .await
             .map_err(|e| e.into_inner())?;
         Ok(())
     }
 
-    /// Subscribes to messages from the state machine manager.
-    pub async fn subscribe(&self) -> Result<impl Stream<Item = Result<String>>> {
+    /// Subscribes to sealed envelopes from the state machine manager.
+    pub async fn subscribe(&self) -> Result<impl Stream<Item = Result<WireEnvelope>>> {
         info!("Subscribing to messages");
         let response = self
             .http_client
@@ -123,10 +310,18 @@ impl SmClient {
         let events = async_sse::decode(response);
         Ok(events.filter_map(|msg| async {
             match msg {
-                Ok(async_sse::Event::Message(msg)) => Some(
-                    String::from_utf8(msg.into_bytes())
-                        .context("SSE message is not valid UTF-8 string"),
-                ),
+                Ok(async_sse::Event::Message(msg)) => {
+                    let text = match String::from_utf8(msg.into_bytes())
+                        .context("SSE message is not valid UTF-8 string")
+                    {
+                        Ok(text) => text,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    Some(
+                        serde_json::from_str::<WireEnvelope>(&text)
+                            .map_err(|e| anyhow!("Failed to parse sealed envelope: {}", e)),
+                    )
+                }
                 Ok(_) => {
                     // Ignore other types of events
                     None
@@ -176,17 +371,27 @@ async fn main() -> Result<()> {
 
     let args: Cli = Cli::from_args();
     let client = SmClient::new(args.address, &args.room).context("Failed to create SmClient")?;
+    let identity = Identity::generate();
 
     match args.cmd {
         Cmd::Broadcast { message } => {
+            let nonce = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+            let ciphertext = hex::encode(message.as_bytes());
+            let payload = signed_payload(0, &nonce, &ciphertext);
+            let signature = identity.ed25519_keypair.sign(&payload);
             client
-                .broadcast(&message)
+                .broadcast(&WireEnvelope {
+                    sender_idx: 0,
+                    nonce,
+                    ciphertext,
+                    ed25519_signature: hex::encode(signature.to_bytes()),
+                })
                 .await
                 .context("Failed to broadcast message")?;
         }
         Cmd::IssueIdx => {
             let index = client
-                .issue_index()
+                .issue_index(&identity.as_party_keys())
                 .await
                 .context("Failed to issue index")?;
             println!("Index: {}", index);
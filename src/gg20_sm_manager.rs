@@ -1,9 +1,11 @@
-use std::collections::hash_map::{Entry, HashMap};
+use std::convert::TryInto;
 use std::sync::{
     atomic::{AtomicU16, Ordering},
     Arc,
 };
+use std::time::Duration;
 
+use ed25519_dalek::{PublicKey as Ed25519PublicKey, Signature, Verifier};
 use log::{debug, error, info, warn};
 use rocket::data::ToByteUnit;
 use rocket::http::Status;
@@ -12,15 +14,25 @@ use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
 use rocket::State;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tokio::sync::{Notify, RwLock};
 
 // Constants for configuration
 const MAX_MESSAGE_SIZE: u64 = 100 * 1024 * 1024; // 100 MB
+/// Prefix used for the sled tree backing each room's durable message log.
+const ROOM_TREE_PREFIX: &str = "room:";
+/// Reserved key holding a room's serialized `RoomMeta`. It is six bytes long,
+/// so it can never collide with a two-byte (`u16`) message index key.
+const META_KEY: &[u8] = b"__meta";
+/// How long a room may sit without activity before it is eligible for GC.
+const ROOM_RETENTION_SECS: i64 = 24 * 60 * 60;
+/// How often the GC sweep runs.
+const GC_INTERVAL: Duration = Duration::from_secs(15 * 60);
 
 /// Handles subscription requests for a specific room
 #[rocket::get("/rooms/<room_id>/subscribe")]
 async fn subscribe<'a>(
-    db: &'a State<Db>,
+    db: &'a State<Arc<Db>>,
     mut shutdown: rocket::Shutdown,
     last_seen_msg: LastEventId,
     room_id: &'a str,
@@ -45,90 +57,367 @@ async fn subscribe<'a>(
     }
 }
 
-/// Issues a unique index for a room
-#[rocket::post("/rooms/<room_id>/issue_unique_idx")]
-async fn issue_idx(db: &State<Db>, room_id: &str) -> Json<IssuedUniqueIdx> {
+/// A party's long-lived public keys, registered once when it joins a room.
+/// `ed25519_public_key` authenticates broadcasts; `x25519_public_key` lets the
+/// other parties derive a per-pair symmetric key via ECDH without the manager
+/// ever holding (or needing) the corresponding private keys.
+///
+/// `x25519_signature` is the registering party's own ed25519 signature over
+/// its `x25519_public_key` (see `gg20_sm_client::PartyKeys::verify_x25519_binding`).
+/// The manager stores and relays it verbatim but never checks it: the whole
+/// point is that a compromised manager must not be able to swap a party's
+/// X25519 key and MITM `secure_channel` undetected, so the binding has to be
+/// verified by the *peers*, not trusted on the manager's say-so.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PartyKeys {
+    ed25519_public_key: String,
+    x25519_public_key: String,
+    // `#[serde(default)]` so loading a `RoomMeta` persisted by a pre-upgrade
+    // manager (no such field) doesn't fail `load_meta`'s deserialization and
+    // silently reset the whole room's party registry and index counter to
+    // defaults. A party whose signature defaults to empty simply fails
+    // `verify_x25519_binding` on the client side until it re-registers.
+    #[serde(default)]
+    x25519_signature: String,
+}
+
+/// Request body for registering a party's keys while obtaining its index.
+#[derive(Deserialize, Debug)]
+struct IssueIdxRequest {
+    ed25519_public_key: String,
+    x25519_public_key: String,
+    #[serde(default)]
+    x25519_signature: String,
+}
+
+/// Issues a unique index for a room and registers the caller's identity keys
+/// against it, so `broadcast` can later verify messages claiming that index.
+#[rocket::post("/rooms/<room_id>/issue_unique_idx", data = "<keys>")]
+async fn issue_idx(
+    db: &State<Arc<Db>>,
+    room_id: &str,
+    keys: Json<IssueIdxRequest>,
+) -> Result<Json<IssuedUniqueIdx>, Status> {
     let room = db.get_room_or_create_empty(room_id).await;
-    let idx = room.issue_unique_idx();
+    let idx = room.issue_unique_idx().await;
+    room.register_party_keys(
+        idx,
+        PartyKeys {
+            ed25519_public_key: keys.ed25519_public_key.clone(),
+            x25519_public_key: keys.x25519_public_key.clone(),
+            x25519_signature: keys.x25519_signature.clone(),
+        },
+    )
+    .await;
     info!("Issued unique index {} for room: {}", idx, room_id);
-    Json::from(IssuedUniqueIdx { unique_idx: idx })
+    Ok(Json::from(IssuedUniqueIdx { unique_idx: idx }))
+}
+
+/// Returns the identity keys registered by every party in a room so a caller
+/// can derive per-recipient X25519 symmetric keys for the parties it talks to.
+#[rocket::get("/rooms/<room_id>/parties")]
+async fn parties(db: &State<Arc<Db>>, room_id: &str) -> Json<HashMap<u16, PartyKeys>> {
+    let room = db.get_room_or_create_empty(room_id).await;
+    Json(room.party_keys().await)
+}
+
+/// A sealed, authenticated message as produced by a party: ciphertext the
+/// manager relays but cannot read, signed by the sender's registered
+/// ed25519 key so the manager (and other parties) can reject forgeries.
+///
+/// `gg20_sm_client`'s wire format didn't match this envelope until the
+/// `secure_channel` change (chunk1-4 in this project's history): any
+/// checkout between this envelope's introduction and that change cannot run
+/// `gg20_signing` against this manager. Noted here for anyone bisecting.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SealedEnvelope {
+    sender_idx: u16,
+    nonce: String,
+    ciphertext: String,
+    ed25519_signature: String,
 }
 
-/// Broadcasts a message to a specific room
-#[rocket::post("/rooms/<room_id>/broadcast", data = "<message>")]
-async fn broadcast(db: &State<Db>, room_id: &str, message: String) -> Status {
+/// Broadcasts a sealed message to a specific room. The manager verifies the
+/// envelope's signature against the sender's registered key but never sees
+/// plaintext, turning it from an open relay into an authenticated transport.
+#[rocket::post("/rooms/<room_id>/broadcast", data = "<envelope>")]
+async fn broadcast(db: &State<Arc<Db>>, room_id: &str, envelope: Json<SealedEnvelope>) -> Status {
     let room = db.get_room_or_create_empty(room_id).await;
-    room.publish(message).await;
-    info!("Broadcasted message to room: {}", room_id);
-    Status::Ok
+    match room.verify_and_publish(envelope.into_inner()).await {
+        Ok(()) => {
+            info!("Broadcasted verified message to room: {}", room_id);
+            Status::Ok
+        }
+        Err(reason) => {
+            warn!("Rejected broadcast to room {}: {}", room_id, reason);
+            Status::BadRequest
+        }
+    }
+}
+
+/// Per-room durable state that is persisted to `sled` so it survives a
+/// restart: the next party index to hand out, each registered party's keys,
+/// and the timestamp of the most recent activity (used for GC).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct RoomMeta {
+    next_party_idx: u16,
+    party_keys: HashMap<u16, PartyKeys>,
+    last_activity_unix: i64,
 }
 
-/// Represents the database of rooms
-struct Db {
+fn now_unix() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Represents the database of rooms. Room event logs live in `sled`, keyed by
+/// room id, so an in-flight keygen/signing session and its SSE replay history
+/// survive a restart of the manager process; `rooms` is just an in-memory
+/// cache of the currently-loaded `Room` handles (subscriber counts are
+/// inherently transient and are not persisted).
+pub struct Db {
+    sled_db: sled::Db,
     rooms: RwLock<HashMap<String, Arc<Room>>>,
 }
 
 impl Db {
-    /// Creates an empty database
-    pub fn empty() -> Self {
+    /// Opens (or creates) the durable room store and rehydrates every room
+    /// that has existing persisted state.
+    pub fn open(sled_db: sled::Db) -> Self {
+        let mut rooms = HashMap::new();
+        for tree_name in sled_db.tree_names() {
+            let name = match std::str::from_utf8(&tree_name) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let room_id = match name.strip_prefix(ROOM_TREE_PREFIX) {
+                Some(room_id) => room_id,
+                None => continue,
+            };
+            let tree = match sled_db.open_tree(&tree_name) {
+                Ok(tree) => tree,
+                Err(e) => {
+                    error!("Failed to open room tree {}: {:?}", name, e);
+                    continue;
+                }
+            };
+            info!("Rehydrating room from disk: {}", room_id);
+            rooms.insert(room_id.to_owned(), Room::rehydrate(room_id.to_owned(), tree));
+        }
+
         Self {
-            rooms: RwLock::new(HashMap::new()),
+            sled_db,
+            rooms: RwLock::new(rooms),
         }
     }
 
-    /// Gets an existing room or creates a new one if it doesn't exist
+    /// Gets an existing room or creates a new one (opening/creating its
+    /// backing sled tree) if it doesn't exist. A room, once created, stays
+    /// cached in `rooms` for as long as its entry exists -- regardless of its
+    /// current subscriber count -- so every caller racing to be the first to
+    /// touch a brand-new `room_id` (as every party's `issue_unique_idx` call
+    /// does, before any of them have subscribed) shares the exact same
+    /// `Room` and its single, authoritative `next_party_idx` counter. Only
+    /// `gc_expired_rooms` removes an entry, and only once it's both
+    /// `is_abandoned()` and stale past its retention window.
     pub async fn get_room_or_create_empty(&self, room_id: &str) -> Arc<Room> {
         let rooms = self.rooms.read().await;
         if let Some(room) = rooms.get(room_id) {
-            if !room.is_abandoned() {
-                return room.clone();
-            }
+            return room.clone();
         }
         drop(rooms);
 
         let mut rooms = self.rooms.write().await;
-        match rooms.entry(room_id.to_owned()) {
-            Entry::Occupied(entry) if !entry.get().is_abandoned() => entry.get().clone(),
-            Entry::Occupied(entry) => {
-                debug!("Cleaning up abandoned room: {}", room_id);
-                let room = Arc::new(Room::empty());
-                *entry.into_mut() = room.clone();
-                room
+        if let Some(room) = rooms.get(room_id) {
+            return room.clone();
+        }
+
+        debug!("Opening durable room: {}", room_id);
+        let tree = self
+            .sled_db
+            .open_tree(format!("{}{}", ROOM_TREE_PREFIX, room_id))
+            .expect("Failed to open room tree");
+        let room = Room::rehydrate(room_id.to_owned(), tree);
+        rooms.insert(room_id.to_owned(), room.clone());
+        room
+    }
+
+    /// Prunes rooms whose backing tree has seen no activity for longer than
+    /// `retention_secs` and that currently have no live subscribers, so
+    /// completed sessions don't accumulate on disk forever.
+    pub async fn gc_expired_rooms(&self, retention_secs: i64) {
+        let now = now_unix();
+        let tree_names = self.sled_db.tree_names();
+        let mut rooms = self.rooms.write().await;
+
+        for tree_name in tree_names {
+            let name = match std::str::from_utf8(&tree_name) {
+                Ok(name) => name.to_owned(),
+                Err(_) => continue,
+            };
+            let room_id = match name.strip_prefix(ROOM_TREE_PREFIX) {
+                Some(room_id) => room_id.to_owned(),
+                None => continue,
+            };
+            if let Some(room) = rooms.get(&room_id) {
+                if !room.is_abandoned() {
+                    continue;
+                }
             }
-            Entry::Vacant(entry) => {
-                debug!("Creating new room: {}", room_id);
-                entry.insert(Arc::new(Room::empty())).clone()
+
+            let tree = match self.sled_db.open_tree(&tree_name) {
+                Ok(tree) => tree,
+                Err(_) => continue,
+            };
+            let meta = Room::load_meta(&tree);
+            if now - meta.last_activity_unix > retention_secs {
+                info!("Pruning expired room: {}", room_id);
+                rooms.remove(&room_id);
+                if let Err(e) = self.sled_db.drop_tree(&tree_name) {
+                    warn!("Failed to drop expired room tree {}: {:?}", room_id, e);
+                }
             }
         }
     }
 }
 
-/// Represents a room where clients can subscribe and broadcast messages
-struct Room {
-    messages: RwLock<Vec<String>>,
+/// Represents a room where clients can subscribe and broadcast messages.
+/// The message log and room metadata are stored in a dedicated sled tree so
+/// they survive a manager restart; only the subscriber count and wake-up
+/// notifier are purely in-memory.
+pub struct Room {
+    room_id: String,
+    tree: sled::Tree,
     message_appeared: Notify,
     subscribers: AtomicU16,
-    next_idx: AtomicU16,
+    next_party_idx: AtomicU16,
+    next_event_idx: AtomicU16,
+    party_keys: RwLock<HashMap<u16, PartyKeys>>,
 }
 
 impl Room {
-    /// Creates an empty room
-    pub fn empty() -> Self {
-        Self {
-            messages: RwLock::new(vec![]),
+    /// Loads a room's persisted metadata and event log from its tree,
+    /// reconstructing in-memory counters from what's on disk.
+    fn rehydrate(room_id: String, tree: sled::Tree) -> Arc<Self> {
+        let meta = Self::load_meta(&tree);
+        let next_event_idx = Self::count_events(&tree);
+
+        Arc::new(Self {
+            room_id,
+            tree,
             message_appeared: Notify::new(),
             subscribers: AtomicU16::new(0),
-            next_idx: AtomicU16::new(1),
+            next_party_idx: AtomicU16::new(meta.next_party_idx.max(1)),
+            next_event_idx: AtomicU16::new(next_event_idx),
+            party_keys: RwLock::new(meta.party_keys),
+        })
+    }
+
+    fn load_meta(tree: &sled::Tree) -> RoomMeta {
+        tree.get(META_KEY)
+            .ok()
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn count_events(tree: &sled::Tree) -> u16 {
+        tree.iter()
+            .keys()
+            .filter_map(|k| k.ok())
+            .filter(|k| k.as_ref() != META_KEY)
+            .count() as u16
+    }
+
+    async fn persist_meta(&self) {
+        let meta = RoomMeta {
+            next_party_idx: self.next_party_idx.load(Ordering::SeqCst),
+            party_keys: self.party_keys.read().await.clone(),
+            last_activity_unix: now_unix(),
+        };
+        if let Ok(serialized) = serde_json::to_vec(&meta) {
+            if let Err(e) = self.tree.insert(META_KEY, serialized) {
+                error!("Failed to persist metadata for room {}: {:?}", self.room_id, e);
+            }
         }
     }
 
-    /// Publishes a new message to the room
+    /// Publishes a new message to the room's durable log.
     pub async fn publish(self: &Arc<Self>, message: String) {
-        let mut messages = self.messages.write().await;
-        messages.push(message);
+        let event_idx = self.next_event_idx.fetch_add(1, Ordering::SeqCst);
+        if let Err(e) = self.tree.insert(event_idx.to_be_bytes(), message.as_bytes()) {
+            error!(
+                "Failed to persist message {} for room {}: {:?}",
+                event_idx, self.room_id, e
+            );
+        }
+        self.persist_meta().await;
         self.message_appeared.notify_waiters();
     }
 
+    /// Registers a party's identity keys against its issued index and
+    /// persists them so a restart doesn't forget who is in the room.
+    pub async fn register_party_keys(self: &Arc<Self>, idx: u16, keys: PartyKeys) {
+        {
+            let mut party_keys = self.party_keys.write().await;
+            party_keys.insert(idx, keys);
+        }
+        self.persist_meta().await;
+    }
+
+    /// Returns a snapshot of every party's registered identity keys.
+    pub async fn party_keys(self: &Arc<Self>) -> HashMap<u16, PartyKeys> {
+        self.party_keys.read().await.clone()
+    }
+
+    /// Verifies `envelope`'s signature against the sender's registered
+    /// ed25519 key, then appends the whole (still-sealed) envelope as an
+    /// opaque JSON string to the durable log.
+    pub async fn verify_and_publish(self: &Arc<Self>, envelope: SealedEnvelope) -> Result<(), String> {
+        let party_keys = self.party_keys.read().await;
+        let keys = party_keys
+            .get(&envelope.sender_idx)
+            .ok_or_else(|| format!("unknown sender index {}", envelope.sender_idx))?
+            .clone();
+        drop(party_keys);
+
+        let public_key_bytes = hex::decode(&keys.ed25519_public_key)
+            .map_err(|_| "sender public key is not valid hex".to_string())?;
+        let public_key = Ed25519PublicKey::from_bytes(&public_key_bytes)
+            .map_err(|_| "sender public key is malformed".to_string())?;
+
+        let signature_bytes = hex::decode(&envelope.ed25519_signature)
+            .map_err(|_| "signature is not valid hex".to_string())?;
+        let signature_bytes: [u8; 64] = signature_bytes
+            .try_into()
+            .map_err(|_| "signature has the wrong length".to_string())?;
+        let signature = Signature::from(signature_bytes);
+
+        let signed_payload = signed_payload(envelope.sender_idx, &envelope.nonce, &envelope.ciphertext);
+        public_key
+            .verify(&signed_payload, &signature)
+            .map_err(|_| "signature verification failed".to_string())?;
+
+        let serialized =
+            serde_json::to_string(&envelope).map_err(|e| format!("failed to serialize envelope: {}", e))?;
+        self.publish(serialized).await;
+        Ok(())
+    }
+
+    /// Checks if the room is abandoned (has no subscribers)
+    pub fn is_abandoned(&self) -> bool {
+        self.subscribers.load(Ordering::SeqCst) == 0
+    }
+
+    /// Issues a unique index for the room and persists the updated counter.
+    pub async fn issue_unique_idx(self: &Arc<Self>) -> u16 {
+        let idx = self.next_party_idx.fetch_add(1, Ordering::SeqCst);
+        self.persist_meta().await;
+        idx
+    }
+
     /// Creates a new subscription to the room
     pub fn subscribe(self: Arc<Self>, last_seen_msg: Option<u16>) -> Subscription {
         let subscribers = self.subscribers.fetch_add(1, Ordering::SeqCst);
@@ -141,16 +430,17 @@ impl Room {
             next_event: last_seen_msg.map(|i| i + 1).unwrap_or(0),
         }
     }
+}
 
-    /// Checks if the room is abandoned (has no subscribers)
-    pub fn is_abandoned(&self) -> bool {
-        self.subscribers.load(Ordering::SeqCst) == 0
-    }
-
-    /// Issues a unique index for the room
-    pub fn issue_unique_idx(&self) -> u16 {
-        self.next_idx.fetch_add(1, Ordering::Relaxed)
-    }
+/// The bytes a party signs to authenticate an envelope: binding the sender
+/// index into the signed payload prevents a valid signature from one message
+/// being replayed under a different claimed sender.
+fn signed_payload(sender_idx: u16, nonce: &str, ciphertext: &str) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(2 + nonce.len() + ciphertext.len());
+    payload.extend_from_slice(&sender_idx.to_be_bytes());
+    payload.extend_from_slice(nonce.as_bytes());
+    payload.extend_from_slice(ciphertext.as_bytes());
+    payload
 }
 
 /// Represents a subscription to a room
@@ -160,17 +450,17 @@ struct Subscription {
 }
 
 impl Subscription {
-    /// Gets the next message in the subscription
+    /// Gets the next message in the subscription, reading straight from the
+    /// durable log so a reconnecting client replays full history (including
+    /// events that arrived before a crash) via the `Last-Event-ID` header.
     pub async fn next(&mut self) -> (u16, String) {
         loop {
-            let history = self.room.messages.read().await;
-            if let Some(msg) = history.get(usize::from(self.next_event)) {
+            if let Ok(Some(bytes)) = self.room.tree.get(self.next_event.to_be_bytes()) {
                 let event_id = self.next_event;
                 self.next_event = event_id + 1;
-                return (event_id, msg.clone());
+                return (event_id, String::from_utf8_lossy(&bytes).into_owned());
             }
             let notification = self.room.message_appeared.notified();
-            drop(history);
             notification.await;
         }
     }
@@ -218,14 +508,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Starting gg20_sm_manager server");
 
+    // Durable room store: defaults to a local sled database so in-flight
+    // sessions survive a restart; override with SM_MANAGER_DB_PATH in tests.
+    let db_path =
+        std::env::var("SM_MANAGER_DB_PATH").unwrap_or_else(|_| "gg20_sm_manager.sled".to_string());
+    let sled_db = sled::open(&db_path).expect("Failed to open durable room database");
+    let db = Arc::new(Db::open(sled_db));
+
+    let gc_db = db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            gc_db.gc_expired_rooms(ROOM_RETENTION_SECS).await;
+        }
+    });
+
     let figment = rocket::Config::figment().merge((
         "limits",
         rocket::data::Limits::new().limit("string", MAX_MESSAGE_SIZE.bytes()),
     ));
 
     let result = rocket::custom(figment)
-        .mount("/", rocket::routes![subscribe, issue_idx, broadcast])
-        .manage(Db::empty())
+        .mount("/", rocket::routes![subscribe, issue_idx, broadcast, parties])
+        .manage(db)
         .launch()
         .await;
 
@@ -1,14 +1,17 @@
 use actix_web::{post, web, HttpResponse, ResponseError};
 use ring::digest::{Context, SHA256};
 use ring::rand::{SecureRandom, SystemRandom};
-use secp256k1::{Message, Secp256k1, SecretKey};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
 use serde::Deserialize;
 use std::sync::Arc;
 use thiserror::Error;
 use uuid::Uuid;
 use zeroize::Zeroize;
 
-use crate::secure_key::{SafeSecretKey, SafeSecretKeyError};
+use crate::challenge::{issue_challenge, verify_and_consume_challenge, ChallengeError, CHALLENGE_TREE};
+use crate::document_crypto::{decrypt_document, encrypt_document, DocumentCryptoError};
+use crate::key_store::{open_secret_key, seal_secret_key, KeyPolicy, KeyStoreError, SealedKeyEnvelope};
+use crate::secure_key::{zeroize_buffer_volatile, SafeSecretKey, SafeSecretKeyError};
 use crate::utils::hex_response;
 
 #[derive(Error, Debug)]
@@ -45,8 +48,83 @@ impl From<SafeSecretKeyError> for AppError {
     }
 }
 
+// Implement conversion from KeyStoreError to AppError. Policy violations are
+// the caller's fault (BadRequest); sealing/opening and master-key setup
+// failures indicate the stored envelope or master key is unusable.
+impl From<KeyStoreError> for AppError {
+    fn from(e: KeyStoreError) -> Self {
+        match e {
+            KeyStoreError::OperationNotAllowed(_)
+            | KeyStoreError::Expired
+            | KeyStoreError::SignatureLimitReached => AppError::BadRequest(e.to_string()),
+            KeyStoreError::SealFailed
+            | KeyStoreError::OpenFailed
+            | KeyStoreError::NonceGenerationFailed
+            | KeyStoreError::SaltInitFailed
+            | KeyStoreError::MissingPassphrase => AppError::KeyHandlingError(e.to_string()),
+        }
+    }
+}
+
+// Implement conversion from DocumentCryptoError to AppError
+impl From<DocumentCryptoError> for AppError {
+    fn from(e: DocumentCryptoError) -> Self {
+        AppError::KeyHandlingError(e.to_string())
+    }
+}
+
+// Implement conversion from ChallengeError to AppError. A missing, expired,
+// invalid, or malformed proof is reported as the caller's fault (BadRequest);
+// `Malformed` also carries challenge-store read/write errors, which surface
+// the same way. Only a failure to generate a nonce is an internal error.
+impl From<ChallengeError> for AppError {
+    fn from(e: ChallengeError) -> Self {
+        match e {
+            ChallengeError::NotFound | ChallengeError::Expired | ChallengeError::InvalidProof => {
+                AppError::BadRequest(e.to_string())
+            }
+            ChallengeError::Malformed(_) => AppError::BadRequest(e.to_string()),
+            ChallengeError::NonceGenerationFailed => AppError::InternalServerError(e.to_string()),
+        }
+    }
+}
+
+/// Request body for `generate_key`. `authorization_pubkey` is the hex-encoded
+/// ed25519 public key of a client-held authorization keypair; its matching
+/// private key must sign a `/challenge` nonce before `sign_message` or
+/// `forget_key` will act on this key, turning the key id into a capability
+/// reference rather than a bearer secret. Policy overrides left unset fall
+/// back to `KeyPolicy::default()`.
+#[derive(Deserialize)]
+struct GenerateKeyRequest {
+    authorization_pubkey: String,
+    #[serde(default)]
+    allowed_operations: Option<Vec<String>>,
+    #[serde(default)]
+    expires_at: Option<i64>,
+    #[serde(default)]
+    max_signatures: Option<u64>,
+}
+
+fn load_envelope(db: &sled::Db, key_id: &str) -> Result<SealedKeyEnvelope, AppError> {
+    let envelope_bytes = db
+        .get(key_id.as_bytes())
+        .map_err(|e| {
+            log::error!("Failed to read key: {:?}", e);
+            AppError::InternalServerError("Failed to read key".into())
+        })?
+        .ok_or_else(|| AppError::NotFound("Key not found".into()))?;
+
+    serde_json::from_slice(&envelope_bytes)
+        .map_err(|_| AppError::InternalServerError("Failed to parse stored key envelope".into()))
+}
+
 #[post("/generate-key")]
-async fn generate_key(db: web::Data<Arc<sled::Db>>) -> Result<HttpResponse, AppError> {
+async fn generate_key(
+    db: web::Data<Arc<sled::Db>>,
+    master_key: web::Data<Arc<[u8; 32]>>,
+    req: web::Json<GenerateKeyRequest>,
+) -> Result<HttpResponse, AppError> {
     let key_id = Uuid::new_v4().to_string();
 
     // Generate a new random secret key using ring for secure randomness
@@ -66,12 +144,32 @@ async fn generate_key(db: web::Data<Arc<sled::Db>>) -> Result<HttpResponse, AppE
     let safe_key = SafeSecretKey::try_from(&secret_key)?;
     drop(safe_key);
 
-    // Store the raw key bytes directly in the database
-    db.insert(key_id.as_bytes(), &secret_key_bytes)
-        .map_err(|e| {
-            log::error!("Failed to store key: {:?}", e);
-            AppError::InternalServerError("Failed to store key".into())
-        })?;
+    let policy = KeyPolicy {
+        allowed_operations: req
+            .allowed_operations
+            .clone()
+            .unwrap_or_else(|| KeyPolicy::default().allowed_operations),
+        expires_at: req.expires_at,
+        max_signatures: req.max_signatures,
+        signature_count: 0,
+    };
+
+    // Seal the key into an AES-GCM envelope under the service master key instead
+    // of storing the raw bytes, so a compromised database file is useless on its own.
+    let envelope = seal_secret_key(
+        &secret_key_bytes,
+        policy,
+        req.authorization_pubkey.clone(),
+        &master_key,
+    )?;
+    let serialized = serde_json::to_vec(&envelope).map_err(|e| {
+        AppError::InternalServerError(format!("Failed to serialize key envelope: {}", e))
+    })?;
+
+    db.insert(key_id.as_bytes(), serialized).map_err(|e| {
+        log::error!("Failed to store key: {:?}", e);
+        AppError::InternalServerError("Failed to store key".into())
+    })?;
 
     // Zeroize the key bytes after use
     secret_key_bytes.zeroize();
@@ -79,36 +177,79 @@ async fn generate_key(db: web::Data<Arc<sled::Db>>) -> Result<HttpResponse, AppE
     Ok(HttpResponse::Ok().json(hex_response("key_id", &key_id)))
 }
 
+#[derive(Deserialize)]
+struct ChallengeRequest {
+    key_id: String,
+}
+
+/// Issues a short-lived, single-use nonce bound to `key_id`. The caller must
+/// sign it with the authorization keypair it registered at `generate_key`
+/// time and present that signature to `sign_message`/`forget_key`.
+#[post("/challenge")]
+async fn challenge_handler(
+    db: web::Data<Arc<sled::Db>>,
+    req: web::Json<ChallengeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let _ = Uuid::parse_str(&req.key_id)
+        .map_err(|_| AppError::BadRequest("Invalid key_id format".into()))?;
+
+    // Ensure the key actually exists before issuing a challenge bound to it.
+    load_envelope(&db, &req.key_id)?;
+
+    let challenges = db.open_tree(CHALLENGE_TREE).map_err(|e| {
+        log::error!("Failed to open challenge store: {:?}", e);
+        AppError::InternalServerError("Failed to open challenge store".into())
+    })?;
+    let nonce = issue_challenge(&challenges, &req.key_id)?;
+
+    Ok(HttpResponse::Ok().json(hex_response("nonce", &hex::encode(nonce))))
+}
+
 #[derive(Deserialize)]
 struct SignMessageRequest {
     key_id: String,
     message: String,
+    nonce: String,
+    proof: String,
 }
 
 #[post("/sign-message")]
 async fn sign_message(
     db: web::Data<Arc<sled::Db>>,
+    master_key: web::Data<Arc<[u8; 32]>>,
     req: web::Json<SignMessageRequest>,
 ) -> Result<HttpResponse, AppError> {
     // Validate the key_id format (UUID in this case)
     let _ = Uuid::parse_str(&req.key_id)
         .map_err(|_| AppError::BadRequest("Invalid key_id format".into()))?;
 
-    // Retrieve the key bytes from the database
-    let key_data = db
-        .get(req.key_id.as_bytes())
-        .map_err(|e| {
-            log::error!("Failed to read key: {:?}", e);
-            AppError::InternalServerError("Failed to read key".into())
-        })?
-        .ok_or_else(|| AppError::NotFound("Key not found".into()))?
-        .to_vec();
+    let mut envelope = load_envelope(&db, &req.key_id)?;
+
+    // Proof of possession: the caller must have signed the nonce from a prior
+    // /challenge call with the authorization key registered for this key_id.
+    let challenges = db.open_tree(CHALLENGE_TREE).map_err(|e| {
+        log::error!("Failed to open challenge store: {:?}", e);
+        AppError::InternalServerError("Failed to open challenge store".into())
+    })?;
+    verify_and_consume_challenge(
+        &challenges,
+        &req.key_id,
+        &envelope.authorization_pubkey,
+        &req.nonce,
+        &req.proof,
+    )?;
+
+    // The policy is the gate: knowing the key_id is not enough to sign with it.
+    envelope.policy.authorize("sign")?;
+
+    let mut secret_key_bytes = open_secret_key(&envelope, &master_key)?;
 
     // Convert the key bytes to SafeSecretKey
-    let secret_key = SecretKey::from_slice(&key_data)
+    let secret_key = SecretKey::from_slice(&secret_key_bytes)
         .map_err(|_| AppError::KeyHandlingError("Failed to recreate SecretKey".into()))?;
 
     let key = SafeSecretKey::try_from(&secret_key)?;
+    secret_key_bytes.zeroize();
 
     // Hash the message using SHA-256
     let mut context = Context::new(&SHA256);
@@ -123,6 +264,16 @@ async fn sign_message(
     let secp = Secp256k1::new();
     let signature = secp.sign_ecdsa(&message, &key);
 
+    // Record the signature against the policy's budget and persist it back.
+    envelope.policy.signature_count += 1;
+    let serialized = serde_json::to_vec(&envelope).map_err(|e| {
+        AppError::InternalServerError(format!("Failed to serialize key envelope: {}", e))
+    })?;
+    db.insert(req.key_id.as_bytes(), serialized).map_err(|e| {
+        log::error!("Failed to persist key policy update: {:?}", e);
+        AppError::InternalServerError("Failed to persist key policy update".into())
+    })?;
+
     Ok(HttpResponse::Ok().json(hex_response(
         "signature",
         &hex::encode(signature.serialize_compact()),
@@ -132,17 +283,43 @@ async fn sign_message(
 #[derive(Deserialize)]
 struct ForgetKeyRequest {
     key_id: String,
+    nonce: String,
+    proof: String,
 }
 
 #[post("/forget-key")]
 async fn forget_key(
     db: web::Data<Arc<sled::Db>>,
+    master_key: web::Data<Arc<[u8; 32]>>,
     req: web::Json<ForgetKeyRequest>,
 ) -> Result<HttpResponse, AppError> {
     // Validate the key_id format (UUID in this case)
     let _ = Uuid::parse_str(&req.key_id)
         .map_err(|_| AppError::BadRequest("Invalid key_id format".into()))?;
 
+    let envelope = load_envelope(&db, &req.key_id)?;
+
+    let challenges = db.open_tree(CHALLENGE_TREE).map_err(|e| {
+        log::error!("Failed to open challenge store: {:?}", e);
+        AppError::InternalServerError("Failed to open challenge store".into())
+    })?;
+    verify_and_consume_challenge(
+        &challenges,
+        &req.key_id,
+        &envelope.authorization_pubkey,
+        &req.nonce,
+        &req.proof,
+    )?;
+
+    envelope.policy.authorize("forget")?;
+
+    // Decrypt the key material before removing its envelope so we can
+    // volatile-zero it in memory rather than just dropping the ciphertext --
+    // the same technique `secure_key::SafeSecretKey` applies to a `SecretKey`,
+    // generalized here to cover any size of decrypted secret buffer.
+    let mut secret_key_bytes = open_secret_key(&envelope, &master_key)?;
+    zeroize_buffer_volatile(&mut secret_key_bytes);
+
     // Attempt to remove the key from the database
     let removed_key = db.remove(req.key_id.as_bytes()).map_err(|e| {
         log::error!("Failed to remove key: {:?}", e);
@@ -156,3 +333,82 @@ async fn forget_key(
 
     Ok(HttpResponse::Ok().body("Key forgotten"))
 }
+
+#[derive(Deserialize)]
+struct EncryptDocumentRequest {
+    key_id: String,
+    plaintext: String,
+}
+
+/// Encrypts `plaintext` to the public key of a managed secp256k1 key via
+/// ECIES, giving the service a general sealed-data capability alongside
+/// signing. The stored secret key itself is never exposed; it is only used
+/// momentarily to derive its public key.
+#[post("/encrypt-document")]
+async fn encrypt_document_handler(
+    db: web::Data<Arc<sled::Db>>,
+    master_key: web::Data<Arc<[u8; 32]>>,
+    req: web::Json<EncryptDocumentRequest>,
+) -> Result<HttpResponse, AppError> {
+    let _ = Uuid::parse_str(&req.key_id)
+        .map_err(|_| AppError::BadRequest("Invalid key_id format".into()))?;
+
+    let envelope = load_envelope(&db, &req.key_id)?;
+    envelope.policy.authorize("encrypt")?;
+
+    let mut secret_key_bytes = open_secret_key(&envelope, &master_key)?;
+    let secret_key = SecretKey::from_slice(&secret_key_bytes)
+        .map_err(|_| AppError::KeyHandlingError("Failed to recreate SecretKey".into()))?;
+    let recipient_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+    secret_key_bytes.zeroize();
+
+    let encrypted = encrypt_document(&recipient_pubkey, req.plaintext.as_bytes())?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "ephemeral_pubkey": hex::encode(encrypted.ephemeral_pubkey),
+        "nonce": hex::encode(encrypted.nonce),
+        "ciphertext": hex::encode(encrypted.ciphertext),
+    })))
+}
+
+#[derive(Deserialize)]
+struct DecryptDocumentRequest {
+    key_id: String,
+    ephemeral_pubkey: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Reverses `encrypt_document_handler` using the stored secret key to
+/// recompute the ECDH shared secret against the sender's ephemeral pubkey.
+#[post("/decrypt-document")]
+async fn decrypt_document_handler(
+    db: web::Data<Arc<sled::Db>>,
+    master_key: web::Data<Arc<[u8; 32]>>,
+    req: web::Json<DecryptDocumentRequest>,
+) -> Result<HttpResponse, AppError> {
+    let _ = Uuid::parse_str(&req.key_id)
+        .map_err(|_| AppError::BadRequest("Invalid key_id format".into()))?;
+
+    let envelope = load_envelope(&db, &req.key_id)?;
+    envelope.policy.authorize("decrypt")?;
+
+    let mut secret_key_bytes = open_secret_key(&envelope, &master_key)?;
+    let secret_key = SecretKey::from_slice(&secret_key_bytes)
+        .map_err(|_| AppError::KeyHandlingError("Failed to recreate SecretKey".into()))?;
+    secret_key_bytes.zeroize();
+
+    let ephemeral_pubkey_bytes = hex::decode(&req.ephemeral_pubkey)
+        .map_err(|_| AppError::BadRequest("Invalid ephemeral_pubkey hex".into()))?;
+    let ephemeral_pubkey = PublicKey::from_slice(&ephemeral_pubkey_bytes)
+        .map_err(|_| AppError::BadRequest("Invalid ephemeral_pubkey".into()))?;
+    let nonce = hex::decode(&req.nonce).map_err(|_| AppError::BadRequest("Invalid nonce hex".into()))?;
+    let ciphertext = hex::decode(&req.ciphertext)
+        .map_err(|_| AppError::BadRequest("Invalid ciphertext hex".into()))?;
+
+    let plaintext = decrypt_document(&secret_key, &ephemeral_pubkey, &nonce, &ciphertext)?;
+    let plaintext = String::from_utf8(plaintext)
+        .map_err(|_| AppError::KeyHandlingError("Decrypted document is not valid UTF-8".into()))?;
+
+    Ok(HttpResponse::Ok().json(hex_response("plaintext", &plaintext)))
+}
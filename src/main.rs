@@ -6,7 +6,10 @@ use actix_web_httpauth::{
 use std::env;
 use std::sync::Arc;
 
+mod challenge;
+mod document_crypto;
 mod handlers;
+mod key_store;
 mod secure_key;
 mod utils;
 
@@ -48,24 +51,39 @@ async fn main() -> std::io::Result<()> {
 
     log::info!("Server starting at http://{}", address);
 
-    // Initialize in-memory database (Sled)
-    log::info!("Initializing in-memory database (Sled).");
-    let db = sled::Config::new()
-        .temporary(true)
-        .open()
-        .expect("Failed to open database");
+    // Open the persistent, sealed key-value store. Every key envelope is
+    // AES-GCM-encrypted under the master key before it ever touches this
+    // database (see `key_store::seal_secret_key`), so the path itself holds
+    // no plaintext secrets; only each envelope's policy and authorization
+    // metadata are readable without the master key.
+    let db_path =
+        env::var("WALLET_DB_PATH").unwrap_or_else(|_| "wallet_as_service_db".to_string());
+    log::info!("Opening persistent key store at {}", db_path);
+    let db = sled::open(&db_path).expect("Failed to open database");
     let db = Arc::new(db);
 
+    // Derive the master key used to seal/open secret keys at rest. Operators
+    // must set WALLET_MASTER_PASSPHRASE (or opt into WALLET_DEV_MODE=1 for
+    // local testing); see `key_store::derive_master_key` for why there is no
+    // silent production fallback.
+    let master_key = Arc::new(
+        key_store::derive_master_key(&db).unwrap_or_else(|e| panic!("Failed to start: {}", e)),
+    );
+
     // Start the Actix-Web server
     HttpServer::new(move || {
         let auth = HttpAuthentication::bearer(validator);
         App::new()
             .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(master_key.clone()))
             .route("/health", web::get().to(health_check)) // Health check endpoint
             .wrap(auth) // Apply authentication middleware to the following routes
             .service(handlers::generate_key)
+            .service(handlers::challenge_handler)
             .service(handlers::sign_message)
             .service(handlers::forget_key)
+            .service(handlers::encrypt_document_handler)
+            .service(handlers::decrypt_document_handler)
     })
     .bind(&address)?
     .run()
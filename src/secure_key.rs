@@ -96,6 +96,18 @@ pub fn drop_secret_key(mut key: SecretKey) {
     }
 }
 
+/// Zeroizes an arbitrary-length buffer of decrypted secret material with the
+/// same volatile-write-then-fence technique `zeroize_secret_key_mut` applies
+/// to a fixed-size `SecretKey`, generalized to cover buffers whose size isn't
+/// known at compile time -- such as a decrypted, serialized GG20 `LocalKey`
+/// share, which is far larger than a 32-byte secp256k1 key.
+pub fn zeroize_buffer_volatile(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        unsafe { write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
 /// Custom Drop implementation ensures the key is zeroized when the SafeSecretKey is dropped.
 impl Drop for SafeSecretKey {
     fn drop(&mut self) {
@@ -140,4 +152,12 @@ mod tests {
         ];
         assert_eq!(safe_clone.serialize_secret(), expected);
     }
+
+    /// Test that a larger, arbitrary-length buffer is fully zeroized.
+    #[test]
+    pub fn zeroize_buffer_volatile_clears_all_bytes() {
+        let mut buf = vec![0xABu8; 4096];
+        zeroize_buffer_volatile(&mut buf);
+        assert!(buf.iter().all(|&b| b == 0));
+    }
 }
@@ -0,0 +1,465 @@
+//! Pluggable transport for `join_computation`'s room channel.
+//!
+//! `gg20_sm_client::join_computation` is hard-wired to HTTP/SSE against a
+//! hosted SM manager: every message round-trips through a central relay,
+//! which adds latency and a mandatory third party. `RoomTransport` factors
+//! the "give me this room's `(index, incoming, outgoing)` channel" step out
+//! behind a trait so a deployment can instead run a direct mesh between
+//! parties when they can all reach each other, while keeping the hosted
+//! relay as the default for the common case.
+//!
+//! Two implementations are provided:
+//! - [`SseTransport`]: the existing manager-relayed SSE/HTTP channel.
+//! - [`FramedTransport`]: a direct, length-delimited TCP mesh (one
+//!   connection per peer pair), handling partial reads via
+//!   `tokio_util`'s `LengthDelimitedCodec`, automatic reconnection, and
+//!   backpressure via bounded per-peer channels.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use log::{debug, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use round_based::Msg;
+
+use crate::gg20_sm_client::{join_computation, Identity};
+
+/// How long to wait before retrying a dropped or failed peer connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// Bound on each peer's outgoing queue; a slow or stalled peer applies
+/// backpressure to senders once its queue fills, rather than buffering
+/// unboundedly.
+const PER_PEER_QUEUE_DEPTH: usize = 256;
+
+/// A boxed, pinned stream of inbound protocol messages. `Pin<Box<_>>` is
+/// `Unpin` regardless of the inner type, so this can be used directly as a
+/// `RoomTransport::Incoming` without callers needing to pin it themselves.
+type BoxedIncoming<M> = Pin<Box<dyn Stream<Item = Result<Msg<M>>> + Send>>;
+/// A boxed, pinned sink of outbound protocol messages; see `BoxedIncoming`.
+type BoxedOutgoing<M> = Pin<Box<dyn Sink<Msg<M>, Error = anyhow::Error> + Send>>;
+
+/// Produces the `(index, incoming, outgoing)` channel `join_computation`
+/// protocols run over, regardless of what physically carries the bytes.
+pub trait RoomTransport<M>
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    type Incoming: Stream<Item = Result<Msg<M>>> + Send + 'static;
+    type Outgoing: Sink<Msg<M>, Error = anyhow::Error> + Send + 'static;
+
+    /// This party's index within the room.
+    fn index(&self) -> u16;
+
+    /// Consumes the transport, handing back its channel halves.
+    fn channels(self) -> (Self::Incoming, Self::Outgoing);
+}
+
+/// The hosted SM manager's SSE/HTTP relay, wrapped to implement
+/// [`RoomTransport`]. This is exactly today's `join_computation` channel.
+pub struct SseTransport<M>
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    index: u16,
+    incoming: BoxedIncoming<M>,
+    outgoing: BoxedOutgoing<M>,
+}
+
+impl<M> SseTransport<M>
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    pub async fn connect(address: surf::Url, room_id: &str, identity: &Identity) -> Result<Self> {
+        let (index, incoming, outgoing) = join_computation::<M>(address, room_id, identity)
+            .await
+            .context("Failed to join computation over SSE transport")?;
+        Ok(Self {
+            index,
+            incoming: Box::pin(incoming),
+            outgoing: Box::pin(outgoing),
+        })
+    }
+}
+
+impl<M> RoomTransport<M> for SseTransport<M>
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    type Incoming = BoxedIncoming<M>;
+    type Outgoing = BoxedOutgoing<M>;
+
+    fn index(&self) -> u16 {
+        self.index
+    }
+
+    fn channels(self) -> (Self::Incoming, Self::Outgoing) {
+        (self.incoming, self.outgoing)
+    }
+}
+
+/// Unpacks any `RoomTransport` into the `(index, incoming, outgoing)` triple
+/// MPC protocol drivers already expect from `join_computation`, so switching
+/// transports is a one-line change at the call site.
+pub fn channels_for<M, T>(transport: T) -> (u16, T::Incoming, T::Outgoing)
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+    T: RoomTransport<M>,
+{
+    let index = transport.index();
+    let (incoming, outgoing) = transport.channels();
+    (index, incoming, outgoing)
+}
+
+/// The handshake frame a dialer sends immediately after connecting, so the
+/// listening side (which only sees an anonymous accepted socket) learns
+/// which party it is talking to.
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    index: u16,
+}
+
+/// A direct, length-delimited TCP mesh: one persistent connection per peer,
+/// with automatic reconnection and a bounded per-peer send queue. Peers with
+/// a smaller index listen for the connection; peers with a larger index dial
+/// out, so each pair ends up with exactly one connection between them.
+pub struct FramedTransport<M>
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    index: u16,
+    incoming: UnboundedReceiverStream<Result<Msg<M>>>,
+    peer_senders: Arc<Mutex<HashMap<u16, mpsc::Sender<Msg<M>>>>>,
+}
+
+impl<M> FramedTransport<M>
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Establishes the mesh: binds `listen_addr` for peers with a larger
+    /// index than `my_index` to connect to us, and dials every peer in
+    /// `peer_addrs` with a smaller index than `my_index`. Each connection is
+    /// kept alive by its own background task that reconnects on failure.
+    pub async fn connect(
+        my_index: u16,
+        listen_addr: SocketAddr,
+        peer_addrs: HashMap<u16, SocketAddr>,
+    ) -> Result<Self> {
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let peer_senders = Arc::new(Mutex::new(HashMap::new()));
+
+        let listener = TcpListener::bind(listen_addr)
+            .await
+            .with_context(|| format!("Failed to bind mesh listener on {}", listen_addr))?;
+        info!("Framed transport listening on {}", listen_addr);
+
+        let expected_dialers: Vec<u16> = peer_addrs
+            .keys()
+            .copied()
+            .filter(|&peer| peer > my_index)
+            .collect();
+        if !expected_dialers.is_empty() {
+            tokio::spawn(accept_loop::<M>(
+                listener,
+                incoming_tx.clone(),
+                peer_senders.clone(),
+            ));
+        }
+
+        for (&peer, &addr) in peer_addrs.iter() {
+            if peer > my_index {
+                continue;
+            }
+            let (outgoing_tx, outgoing_rx) = mpsc::channel(PER_PEER_QUEUE_DEPTH);
+            peer_senders.lock().unwrap().insert(peer, outgoing_tx);
+            tokio::spawn(dial_loop::<M>(
+                addr,
+                my_index,
+                incoming_tx.clone(),
+                outgoing_rx,
+            ));
+        }
+
+        Ok(Self {
+            index: my_index,
+            incoming: UnboundedReceiverStream::new(incoming_rx),
+            peer_senders,
+        })
+    }
+}
+
+impl<M> RoomTransport<M> for FramedTransport<M>
+where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    type Incoming = UnboundedReceiverStream<Result<Msg<M>>>;
+    type Outgoing = BoxedOutgoing<M>;
+
+    fn index(&self) -> u16 {
+        self.index
+    }
+
+    fn channels(self) -> (Self::Incoming, Self::Outgoing) {
+        let peer_senders = self.peer_senders;
+        let outgoing = futures::sink::unfold(peer_senders, |peer_senders, message: Msg<M>| async move {
+            // Snapshot the senders registered so far rather than holding the
+            // lock across the `.send().await` calls below -- a peer accepted
+            // after this message started sending shouldn't block on the lock,
+            // and registration only ever adds/replaces entries.
+            let targets: Vec<(u16, mpsc::Sender<Msg<M>>)> = {
+                let senders = peer_senders.lock().unwrap();
+                match message.receiver {
+                    Some(receiver) => senders
+                        .get(&receiver)
+                        .map(|sender| vec![(receiver, sender.clone())])
+                        .unwrap_or_default(),
+                    None => senders
+                        .iter()
+                        .map(|(&peer, sender)| (peer, sender.clone()))
+                        .collect(),
+                }
+            };
+            for (peer, sender) in targets {
+                let outcome = sender
+                    .send(Msg {
+                        sender: message.sender,
+                        receiver: message.receiver,
+                        body: clone_unchecked(&message.body)?,
+                    })
+                    .await;
+                // A single dead peer (mid-reconnect, or gone for good) must
+                // not abort delivery to the rest of the room -- `accept_loop`
+                // re-registers a fresh sender when an accepted peer
+                // reconnects, so drop only the stale entry here rather than
+                // failing the whole sink.
+                if outcome.is_err() {
+                    warn!("mesh connection to party {} is gone, dropping message", peer);
+                    peer_senders.lock().unwrap().remove(&peer);
+                }
+            }
+            Ok::<_, anyhow::Error>(peer_senders)
+        });
+        (self.incoming, Box::pin(outgoing))
+    }
+}
+
+/// `Msg<M>` isn't `Clone` for arbitrary `M`, but every message we fan out
+/// here only needs to survive a JSON round-trip to be duplicated across
+/// peers, so re-serializing is as good as cloning.
+fn clone_unchecked<M: Serialize + DeserializeOwned>(body: &M) -> Result<M> {
+    let bytes = serde_json::to_vec(body).context("Failed to serialize message for fan-out")?;
+    serde_json::from_slice(&bytes).context("Failed to re-deserialize message for fan-out")
+}
+
+/// Accepts inbound connections from higher-indexed peers, reads each one's
+/// `Hello` handshake frame, and spawns a handler for it. Keeps accepting for
+/// the lifetime of the transport so a peer that reconnects is picked up
+/// again.
+async fn accept_loop<M>(
+    listener: TcpListener,
+    incoming_tx: mpsc::UnboundedSender<Result<Msg<M>>>,
+    peer_senders: Arc<Mutex<HashMap<u16, mpsc::Sender<Msg<M>>>>>,
+) where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept mesh connection: {}", e);
+                continue;
+            }
+        };
+        debug!("Accepted mesh connection from {}", peer_addr);
+
+        let incoming_tx = incoming_tx.clone();
+        let peer_senders = peer_senders.clone();
+        tokio::spawn(async move {
+            let mut framed = Framed::new(stream, LengthDelimitedCodec::new());
+            let hello = match framed.next().await {
+                Some(Ok(frame)) => frame,
+                _ => {
+                    warn!("Mesh peer {} disconnected before handshake", peer_addr);
+                    return;
+                }
+            };
+            let sender_index = match serde_json::from_slice::<Hello>(&hello) {
+                Ok(hello) => hello.index,
+                Err(e) => {
+                    warn!("Malformed handshake from {}: {}", peer_addr, e);
+                    return;
+                }
+            };
+            info!("Mesh peer {} identified as party {}", peer_addr, sender_index);
+
+            // Register a fresh outgoing queue for this peer so the sink in
+            // `FramedTransport::channels` can reach it -- without this, the
+            // accept side of a pair could only ever receive, never reply.
+            let (outgoing_tx, outgoing_rx) = mpsc::channel(PER_PEER_QUEUE_DEPTH);
+            peer_senders
+                .lock()
+                .unwrap()
+                .insert(sender_index, outgoing_tx.clone());
+
+            pump_accepted_connection(framed, incoming_tx, outgoing_rx).await;
+
+            // The connection is gone; drop its (now-closed) sender so the
+            // outgoing sink sees an absent peer rather than a dead one until
+            // `accept_loop` picks up a reconnect and re-registers it above.
+            // Only remove the entry if it's still ours -- a faster-reconnecting
+            // handler for the same peer may already have installed its own
+            // fresh sender, which this (older, now-finished) handler must not
+            // clobber.
+            let mut senders = peer_senders.lock().unwrap();
+            if senders
+                .get(&sender_index)
+                .map_or(false, |current| current.same_channel(&outgoing_tx))
+            {
+                senders.remove(&sender_index);
+            }
+        });
+    }
+}
+
+/// Reads incoming frames from an accepted connection while draining its
+/// registered outgoing queue, mirroring `dial_loop`'s inner pump but without
+/// the reconnect wrapper -- `accept_loop` already re-accepts a fresh
+/// connection (and re-registers a fresh queue) if this one drops.
+async fn pump_accepted_connection<M>(
+    mut framed: Framed<TcpStream, LengthDelimitedCodec>,
+    incoming_tx: mpsc::UnboundedSender<Result<Msg<M>>>,
+    mut outgoing_rx: mpsc::Receiver<Msg<M>>,
+) where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    loop {
+        tokio::select! {
+            frame = framed.next() => {
+                match frame {
+                    Some(frame) => {
+                        let result = frame
+                            .context("Mesh connection read error")
+                            .and_then(|bytes| {
+                                serde_json::from_slice::<Msg<M>>(&bytes)
+                                    .context("Failed to deserialize mesh message")
+                            });
+                        let is_err = result.is_err();
+                        if incoming_tx.send(result).is_err() {
+                            // Receiver half was dropped; nothing left to forward to.
+                            return;
+                        }
+                        if is_err {
+                            return;
+                        }
+                    }
+                    None => return,
+                }
+            }
+            outgoing = outgoing_rx.recv() => {
+                let message = match outgoing {
+                    Some(message) => message,
+                    None => return, // Sender half dropped; nothing left to send.
+                };
+                let serialized = match serde_json::to_vec(&message) {
+                    Ok(serialized) => serialized,
+                    Err(e) => {
+                        warn!("Failed to serialize message for accepted peer: {}", e);
+                        continue;
+                    }
+                };
+                if framed.send(Bytes::from(serialized)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Dials a peer, handshakes, and pumps outgoing messages to it while
+/// forwarding its frames into the merged incoming channel. Reconnects with a
+/// fixed delay whenever the connection drops, so a transient network blip
+/// doesn't permanently sever the pair.
+async fn dial_loop<M>(
+    addr: SocketAddr,
+    my_index: u16,
+    incoming_tx: mpsc::UnboundedSender<Result<Msg<M>>>,
+    mut outgoing_rx: mpsc::Receiver<Msg<M>>,
+) where
+    M: Serialize + DeserializeOwned + Send + 'static,
+{
+    loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => {
+                debug!("Connected to mesh peer at {}", addr);
+                let mut framed = Framed::new(stream, LengthDelimitedCodec::new());
+
+                let hello = match serde_json::to_vec(&Hello { index: my_index }) {
+                    Ok(hello) => hello,
+                    Err(e) => {
+                        warn!("Failed to encode handshake for {}: {}", addr, e);
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        continue;
+                    }
+                };
+                if framed.send(Bytes::from(hello)).await.is_err() {
+                    warn!("Failed to send handshake to {}", addr);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+
+                // Pump outgoing messages until the connection breaks, then
+                // fall through to reconnect; any message not yet sent stays
+                // queued in `outgoing_rx` for the next connection attempt.
+                loop {
+                    tokio::select! {
+                        incoming = framed.next() => {
+                            match incoming {
+                                Some(Ok(bytes)) => {
+                                    let result = serde_json::from_slice::<Msg<M>>(&bytes)
+                                        .context("Failed to deserialize mesh message");
+                                    if incoming_tx.send(result).is_err() {
+                                        return;
+                                    }
+                                }
+                                _ => break,
+                            }
+                        }
+                        outgoing = outgoing_rx.recv() => {
+                            let message = match outgoing {
+                                Some(message) => message,
+                                None => return, // Sender half dropped; nothing left to send.
+                            };
+                            let serialized = match serde_json::to_vec(&message) {
+                                Ok(serialized) => serialized,
+                                Err(e) => {
+                                    warn!("Failed to serialize message for {}: {}", addr, e);
+                                    continue;
+                                }
+                            };
+                            if framed.send(Bytes::from(serialized)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                warn!("Mesh connection to {} dropped, reconnecting", addr);
+            }
+            Err(e) => {
+                warn!("Failed to connect to mesh peer {}: {}", addr, e);
+            }
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}